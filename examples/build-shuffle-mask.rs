@@ -23,12 +23,12 @@ fn main() {
     let max: u16 = 16383; // 14 bits
 
     println!(
-        "pub (crate) const SHUFFLE_MASK: [[u8; 16]; {}] = [",
+        "pub (crate) static SHUFFLE_MASK: [[u8; 16]; {}] = [",
         max + 1
     );
 
     for i in 0..=max {
-        let mask = build_mask_128(i as u16);
+        let mask = build_mask_128(i);
         println!("    {:?},", mask);
     }
 
@@ -50,7 +50,7 @@ fn build_mask_128(found_mask: u16) -> [u8; 16] {
                 out_i -= 2;
             }
         }
-        mask = mask << 1;
+        mask <<= 1;
         out_i += 1;
     }
     shuffle_map
@@ -0,0 +1,461 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::simd::{self, Hook, SimdVector};
+use crate::validation::{ValidationError, ValidationRules};
+use crate::Mode;
+
+/// This is an AVX2 + POPCNT implementation of URL decode.
+///
+/// The result is appended to `dst`, keeping any existing contents.
+///
+/// Bytes are classified 32 at a time. AVX2 can only shuffle within each 128-bit
+/// lane so the escapes are removed from each half using the SSE4.1 implementation.
+///
+/// No validation of UTF-8 data is performed so if a string is desired,
+/// it should be sanitised with eg [String::from_utf8_lossy]
+/// (https://doc.rust-lang.org/std/string/struct.String.html#method.from_utf8_lossy)
+///
+/// # Safety
+///
+/// The CPU must support AVX2 and POPCNT.
+#[target_feature(enable = "avx2")]
+#[target_feature(enable = "popcnt")]
+pub unsafe fn url_decode(src: &[u8], dst: &mut Vec<u8>) {
+    simd::url_decode::<__m256i>(src, dst)
+}
+
+/// Decode a URL-encoded value and append the result to `dst`, passing the escapes
+/// of each vector through `hook`.
+#[target_feature(enable = "avx2")]
+#[target_feature(enable = "popcnt")]
+pub(crate) unsafe fn url_decode_with<H: Hook<__m256i>>(src: &[u8], dst: &mut Vec<u8>, hook: &mut H) {
+    simd::url_decode_with(src, dst, hook)
+}
+
+/// Decode a URL-encoded value to `dst`, which has room for `capacity` bytes.
+///
+/// `capacity` must be at least `src.len()`. Returns the number of bytes written.
+#[target_feature(enable = "avx2")]
+#[target_feature(enable = "popcnt")]
+pub(crate) unsafe fn decode_to(src: &[u8], dst: *mut u8, capacity: usize) -> usize {
+    simd::decode_to::<__m256i>(src, dst, capacity)
+}
+
+/// Count the bytes that [`url_decode`] would output for `src` without decoding it.
+///
+/// # Safety
+///
+/// The CPU must support AVX2 and POPCNT.
+#[target_feature(enable = "avx2")]
+#[target_feature(enable = "popcnt")]
+pub unsafe fn decoded_len(src: &[u8]) -> usize {
+    simd::decoded_len::<__m256i>(src)
+}
+
+/// Check if decoding `src` in the given mode would change it.
+///
+/// # Safety
+///
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn needs_decoding(src: &[u8], mode: Mode) -> bool {
+    simd::needs_decoding::<__m256i>(src, mode)
+}
+
+/// Check that every `%` in `src` starts a valid escape, and optionally that every
+/// byte is allowed by RFC 3986.
+///
+/// # Safety
+///
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn validate(src: &[u8], rules: &ValidationRules) -> Result<(), ValidationError> {
+    simd::validate::<__m256i>(src, rules)
+}
+
+/// Find the percent symbols and valid escapes in the first 32 bytes of `src`.
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn escape_masks(src: &[u8]) -> (usize, u32, u32) {
+    simd::escape_masks::<__m256i>(src)
+}
+
+impl SimdVector for __m256i {
+    const LANES: usize = 32;
+
+    #[inline(always)]
+    unsafe fn splat(byte: u8) -> Self {
+        _mm256_set1_epi8(byte as i8)
+    }
+
+    #[inline(always)]
+    unsafe fn load(ptr: *const u8) -> Self {
+        _mm256_loadu_si256(ptr as *const __m256i)
+    }
+
+    #[inline(always)]
+    unsafe fn load_partial(src: &[u8]) -> Self {
+        if src.len() > 16 {
+            let lo = __m128i::load(src.as_ptr());
+            let hi = __m128i::load_partial(src.get_unchecked(16..));
+            _mm256_set_m128i(hi, lo)
+        } else {
+            _mm256_zextsi128_si256(__m128i::load_partial(src))
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut u8) {
+        _mm256_storeu_si256(ptr as *mut __m256i, self)
+    }
+
+    #[inline(always)]
+    unsafe fn cmpeq(self, other: Self) -> Self {
+        _mm256_cmpeq_epi8(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn cmpgt(self, other: Self) -> Self {
+        _mm256_cmpgt_epi8(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn cmplt(self, other: Self) -> Self {
+        _mm256_cmpgt_epi8(other, self)
+    }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self {
+        _mm256_and_si256(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self {
+        _mm256_or_si256(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        _mm256_add_epi8(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        _mm256_sub_epi8(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn blend(self, other: Self, mask: Self) -> Self {
+        _mm256_blendv_epi8(self, other, mask)
+    }
+
+    #[inline(always)]
+    unsafe fn shl16<const BITS: i32>(self) -> Self {
+        _mm256_slli_epi16::<BITS>(self)
+    }
+
+    #[inline(always)]
+    unsafe fn shift_down<const BYTES: i32>(self) -> Self {
+        // Shifts within 128-bit lanes so the upper half is moved into the lower
+        // lane first. The upper lane is shifted along with zeros.
+        let hi = _mm256_permute2x128_si256::<0x81>(self, self);
+        _mm256_alignr_epi8::<BYTES>(hi, self)
+    }
+
+    #[inline(always)]
+    unsafe fn shuffle(self, indices: Self) -> Self {
+        _mm256_shuffle_epi8(self, indices)
+    }
+
+    #[inline(always)]
+    unsafe fn movemask(self) -> u32 {
+        _mm256_movemask_epi8(self) as u32
+    }
+
+    #[inline(always)]
+    unsafe fn is_zero(self) -> bool {
+        _mm256_testz_si256(self, self) > 0
+    }
+
+    /// Compacts each 128-bit half in turn. Escapes at the end of the first half
+    /// are carried into the second.
+    #[inline(always)]
+    unsafe fn compact_store(self, found_mask: u32, carry: usize, dst: *mut u8) -> (usize, usize) {
+        let lo = _mm256_castsi256_si128(self);
+        let hi = _mm256_extracti128_si256::<1>(self);
+
+        let (len_lo, carry) = lo.compact_store(found_mask & 0xffff, carry, dst);
+        let (len_hi, carry) = hi.compact_store(found_mask >> 16, carry, dst.add(len_lo));
+
+        (len_lo + len_hi, carry)
+    }
+
+    #[inline(always)]
+    unsafe fn print(self, _msg: &str) {
+        print_m256i!(_msg, self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    use super::{url_decode, decoded_len, needs_decoding, validate};
+    use crate::test_util::{assert_decoded_len, assert_matches_fallback, assert_needs_decoding, assert_validate, Backend};
+
+    fn backend() -> Backend {
+        Backend {
+            url_decode: |src, dst| unsafe { url_decode(src, dst) },
+            decoded_len: |src| unsafe { decoded_len(src) },
+            needs_decoding: |src, mode| unsafe { needs_decoding(src, mode) },
+            validate: |src, rules| unsafe { validate(src, rules) },
+        }
+    }
+
+    #[test]
+    fn url_decode_space() {
+        let v = b"%20\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b" \0\0\0\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_A() {
+        let v = b"%41\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"A\0\0\0\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AB() {
+        let v = b"%41%42\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"AB\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AaBb() {
+        let v = b"%41a%42b\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"AaBb\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AaBb_numbers() {
+        let v = b"%41a%42b12345678";
+        let mut result = Vec::new();
+
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"AaBb12345678", &result[..])
+    }
+
+    #[test]
+    fn url_decode_upper_hex_KaLb_numbers() {
+        let v = b"%4Ba%4Cb12345678";
+        let mut result = Vec::new();
+
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"KaLb12345678", &result[..])
+    }
+
+    #[test]
+    fn url_decode_lower_hex_KaLb_numbers() {
+        let v = b"%4ba%4cb12345678";
+        let mut result = Vec::new();
+
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"KaLb12345678", &result[..])
+    }
+
+    #[test]
+    fn test_decode_invalid_chars() {
+        let mut result = Vec::new();
+
+        let v = b"%%12345678901234";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"%\x12345678901234", &result[..]);
+
+        let v = b"%1%2345678901234";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"%1\x2345678901234", &result[..]);
+
+        let v = b"%%%1234567890123";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"%%\x1234567890123", &result[..]);
+
+        let v = b"%-12345678901234";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"%-12345678901234", &result[..]);
+
+        let v = b"%1-2345678901234";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"%1-2345678901234", &result[..]);
+    }
+
+    #[test]
+    fn test_end_percent() {
+        let mut result = Vec::new();
+
+        // last char of block is %
+        let v = b"aaaaaaaaaaaaaaa%";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaa%", &result[..]);
+
+        // 2nd last char of block is %
+        let v = b"aaaaaaaaaaaaaa%a";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaa%a", &result[..]);
+    }
+
+    #[test]
+    fn test_split_percent() {
+        let mut result = Vec::new();
+
+        // last char of block is %
+        let v = b"aaaaaaaaaaaaaaa%aaaaaaaaaaaaaaaa";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaa\xAAaaaaaaaaaaaaaa", &result[..]);
+
+        // 2nd last char of block is %
+        let v = b"aaaaaaaaaaaaaa%aaaaaaaaaaaaaaaaa";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaa\xAAaaaaaaaaaaaaaaa", &result[..]);
+    }
+
+    #[test]
+    fn test_out_of_ascii_hex() {
+        let mut result = Vec::new();
+
+        let v = b"%AAaaaaaaaaaaaaa";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"\xAAaaaaaaaaaaaaa", &result[..]);
+    }
+
+    #[test]
+    fn test_replace_plus() {
+        let mut result = Vec::new();
+
+        let v = b"a+a+a+a+a+a+a+a+";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"a a a a a a a a ", &result[..]);
+    }
+
+    #[test]
+    fn test_random_junk() {
+        let mut result = Vec::new();
+
+        let v = b"\xCF%%sA\x00`A%5%%6%6\xEF";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"\xCF%%sA\x00`A%5%%6%6\xEF", &result[..]);
+    }
+
+    #[test]
+    fn test_append() {
+        let mut result = b"abc".to_vec();
+
+        let v = b"%41a%42b12345678%43";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"abcAaBb12345678C", &result[..]);
+    }
+
+    #[test]
+    fn test_short() {
+        let mut result = Vec::new();
+
+        let v = b"%41";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"A", &result[..]);
+
+        let v = b"a+b%4";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"a b%4", &result[..]);
+
+        let v = b"aaaaaaaaaaaaa%4";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaa%4", &result[..]);
+
+        let v = b"";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"", &result[..]);
+    }
+
+    #[test]
+    fn test_tail() {
+        let mut result = Vec::new();
+
+        // last char of block is % and is decoded in the tail
+        let v = b"aaaaaaaaaaaaaaa%41";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaA", &result[..]);
+
+        let v = b"aaaaaaaaaaaaaaaa%41+%4";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaaA %4", &result[..]);
+    }
+
+    #[test]
+    fn test_carry() {
+        let mut result = Vec::new();
+
+        // escape at the 15th byte of the first half
+        let v = b"aaaaaaaaaaaaaa%41aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaAaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", &result[..]);
+
+        // escape at the last byte of the first half
+        let v = b"aaaaaaaaaaaaaaa%41aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaAaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", &result[..]);
+
+        // escape at the last byte of the first vector
+        let v = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%41aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaAaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", &result[..]);
+
+        // escape at the 31st byte of the second vector
+        let v = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%41aaaaaaaaaa";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaAaaaaaaaaaa", &result[..]);
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        assert_matches_fallback(backend(), 0..150, 50);
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        assert_decoded_len(backend());
+    }
+
+    #[test]
+    fn test_needs_decoding() {
+        assert_needs_decoding(backend());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_validate(backend());
+    }
+}
@@ -0,0 +1,106 @@
+//! Decoding repeatedly until the value no longer changes, to see through values which
+//! have been encoded more than once.
+
+use std::mem;
+
+use crate::mode::url_decode_mode;
+use crate::{needs_decoding, url_decode, Mode};
+
+/// The result of [`canonicalize`](crate::canonicalize).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Canonical {
+    /// The value after the last pass.
+    pub value: Vec<u8>,
+    /// Number of passes which decoded an escape.
+    pub passes: usize,
+    /// Whether another pass would leave the value unchanged. This is `false` if
+    /// `max_passes` was reached first.
+    pub complete: bool,
+}
+
+impl Canonical {
+    /// Check if the input was encoded more than once, eg `%252e` for `.`.
+    pub fn is_multiply_encoded(&self) -> bool {
+        self.passes > 1
+    }
+}
+
+/// Decode `src` until it no longer changes, up to `max_passes` times.
+///
+/// `+` is only a space in the original value. Once decoded, a `+` came from `%2B`
+/// and is kept, so later passes only decode escapes. A pass is only counted if it
+/// decoded an escape.
+pub(crate) fn canonicalize(src: &[u8], max_passes: usize) -> Canonical {
+    if max_passes == 0 {
+        return Canonical {
+            value: src.to_vec(),
+            passes: 0,
+            complete: !needs_decoding(src, Mode::Form),
+        };
+    }
+
+    let mut value = Vec::with_capacity(src.len());
+    url_decode(src, &mut value);
+    let mut passes = if needs_decoding(src, Mode::Path) { 1 } else { 0 };
+
+    // Decode back and forth between two buffers.
+    let mut scratch = Vec::with_capacity(value.len());
+    while passes < max_passes && needs_decoding(&value, Mode::Path) {
+        scratch.clear();
+        url_decode_mode(&value, &mut scratch, Mode::Path);
+        mem::swap(&mut value, &mut scratch);
+        passes += 1;
+    }
+
+    let complete = !needs_decoding(&value, Mode::Path);
+    Canonical { value, passes, complete }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::canonicalize;
+
+    #[test]
+    fn test_canonicalize() {
+        let result = canonicalize(b"/a/b", 3);
+        assert_eq!((&b"/a/b"[..], 0, true), (&result.value[..], result.passes, result.complete));
+
+        let result = canonicalize(b"%2e%2e/", 3);
+        assert_eq!((&b"../"[..], 1, true), (&result.value[..], result.passes, result.complete));
+        assert!(!result.is_multiply_encoded());
+
+        let result = canonicalize(b"%252e%252e/", 3);
+        assert_eq!((&b"../"[..], 2, true), (&result.value[..], result.passes, result.complete));
+        assert!(result.is_multiply_encoded());
+
+        let result = canonicalize(b"%25252e", 3);
+        assert_eq!((&b"."[..], 3, true), (&result.value[..], result.passes, result.complete));
+    }
+
+    #[test]
+    fn test_canonicalize_max_passes() {
+        let result = canonicalize(b"%25252e", 2);
+        assert_eq!((&b"%2e"[..], 2, false), (&result.value[..], result.passes, result.complete));
+
+        let result = canonicalize(b"%2e", 0);
+        assert_eq!((&b"%2e"[..], 0, false), (&result.value[..], result.passes, result.complete));
+    }
+
+    #[test]
+    fn test_canonicalize_plus() {
+        // `%2B` is a `+` after one pass, which is not decoded again.
+        let result = canonicalize(b"a%2Bb", 3);
+        assert_eq!((&b"a+b"[..], 1, true), (&result.value[..], result.passes, result.complete));
+
+        let result = canonicalize(b"C%2B%2B", 4);
+        assert_eq!((&b"C++"[..], 1, true), (&result.value[..], result.passes, result.complete));
+        assert!(!result.is_multiply_encoded());
+
+        let result = canonicalize(b"%252B+", 3);
+        assert_eq!((&b"+ "[..], 2, true), (&result.value[..], result.passes, result.complete));
+
+        // Decoding `+` alone doesn't count as a pass.
+        let result = canonicalize(b"a+b", 3);
+        assert_eq!((&b"a b"[..], 0, true), (&result.value[..], result.passes, result.complete));
+    }
+}
@@ -0,0 +1,387 @@
+//! Decoding in blocks into a buffer on the stack, so that the decoded form of input
+//! can be inspected without allocating.
+
+use std::cmp;
+use std::hash::{Hash, Hasher};
+
+/// Maximum number of bytes of input decoded at a time.
+const BLOCK_LEN: usize = block_len();
+
+/// One vector of the implementation in use, so that comparisons can stop after the
+/// first vector with a mismatch.
+const fn block_len() -> usize {
+    dispatch! {
+        avx2 => 32,
+        sse41 => 16,
+        portable => 16,
+        swar => 8,
+    }
+}
+
+/// Decode `src` a block at a time and call `f` with each decoded block.
+///
+/// Stops early if `f` returns `false`. Returns `true` if every block was visited.
+pub(crate) fn for_each_block(src: &[u8], mut f: impl FnMut(&[u8]) -> bool) -> bool {
+    let mut blocks = Blocks::new(src);
+
+    loop {
+        let block = blocks.current();
+        let len = block.len();
+
+        if len == 0 {
+            return true;
+        }
+        if !f(block) {
+            return false;
+        }
+        blocks.consume(len);
+    }
+}
+
+/// Decodes input a block at a time into a buffer on the stack, for reading the
+/// decoded bytes in pieces of any length.
+struct Blocks<'a> {
+    src: &'a [u8],
+    buf: [u8; BLOCK_LEN],
+    /// The decoded bytes in `buf` which haven't been consumed.
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Blocks<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        Blocks { src, buf: [0; BLOCK_LEN], start: 0, end: 0 }
+    }
+
+    /// The decoded bytes which haven't been consumed, decoding the next block if
+    /// needed. Empty at the end of the input.
+    #[inline]
+    fn current(&mut self) -> &[u8] {
+        while self.start == self.end && !self.src.is_empty() {
+            let end = block_end(self.src);
+            self.end = decode_to(&self.src[..end], &mut self.buf);
+            self.start = 0;
+            self.src = &self.src[end..];
+        }
+
+        &self.buf[self.start..self.end]
+    }
+
+    #[inline]
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+    }
+}
+
+/// Find where to split `src` after at most `BLOCK_LEN` bytes without splitting an escape.
+#[inline]
+fn block_end(src: &[u8]) -> usize {
+    if src.len() <= BLOCK_LEN {
+        return src.len();
+    }
+
+    // Hex digits are never `%` so an escape can't start before a `%` in the last
+    // 2 bytes and end after it.
+    if src[BLOCK_LEN - 2] == b'%' {
+        BLOCK_LEN - 2
+    } else if src[BLOCK_LEN - 1] == b'%' {
+        BLOCK_LEN - 1
+    } else {
+        BLOCK_LEN
+    }
+}
+
+/// Decode `src` into `buf`, which must be at least as long as `src`.
+///
+/// Returns the number of bytes written.
+#[inline]
+fn decode_to(src: &[u8], buf: &mut [u8]) -> usize {
+    assert!(buf.len() >= src.len());
+
+    dispatch! {
+        avx2 => unsafe { crate::avx2::decode_to(src, buf.as_mut_ptr(), buf.len()) },
+        sse41 => unsafe { crate::sse41::decode_to(src, buf.as_mut_ptr(), buf.len()) },
+        portable => unsafe { crate::portable::decode_to(src, buf.as_mut_ptr(), buf.len()) },
+        swar => unsafe { crate::swar::decode_to(src, buf.as_mut_ptr()) },
+    }
+}
+
+/// Check if `encoded` decodes to `expected`.
+pub(crate) fn decoded_eq(encoded: &[u8], expected: &[u8]) -> bool {
+    // Each byte of output comes from 1 to 3 bytes of input.
+    if expected.len() > encoded.len() || expected.len() < encoded.len() / 3 {
+        return false;
+    }
+
+    let mut rest = expected;
+    let visited = for_each_block(encoded, |block| {
+        if rest.len() < block.len() || &rest[..block.len()] != block {
+            return false;
+        }
+        rest = &rest[block.len()..];
+        true
+    });
+
+    visited && rest.is_empty()
+}
+
+/// Check if `encoded` decodes to a value starting with `prefix`.
+pub(crate) fn decoded_starts_with(encoded: &[u8], prefix: &[u8]) -> bool {
+    if prefix.len() > encoded.len() {
+        return false;
+    }
+
+    let mut rest = prefix;
+    let mut matched = true;
+    for_each_block(encoded, |block| {
+        let len = cmp::min(block.len(), rest.len());
+        matched = block[..len] == rest[..len];
+        rest = &rest[len..];
+        matched && !rest.is_empty()
+    });
+
+    matched && rest.is_empty()
+}
+
+/// Check if `a` and `b` decode to the same value.
+pub(crate) fn decoded_values_eq(a: &[u8], b: &[u8]) -> bool {
+    if a == b {
+        return true;
+    }
+
+    let mut a = Blocks::new(a);
+    let mut b = Blocks::new(b);
+
+    loop {
+        let x = a.current();
+        let y = b.current();
+        if x.is_empty() || y.is_empty() {
+            return x.is_empty() && y.is_empty();
+        }
+
+        let len = cmp::min(x.len(), y.len());
+        if x[..len] != y[..len] {
+            return false;
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
+/// Number of decoded bytes passed to each call of `Hasher::write`.
+const HASH_BLOCK_LEN: usize = 64;
+
+/// Feed the decoded length of `src` and then its decoded bytes to `state`.
+///
+/// The bytes are written in blocks of `HASH_BLOCK_LEN` whatever the blocks of input,
+/// so that the calls to the hasher only depend on the decoded value.
+pub(crate) fn decoded_hash<H: Hasher>(src: &[u8], state: &mut H) {
+    state.write_usize(crate::decoded_len(src));
+
+    let mut staged = [0u8; HASH_BLOCK_LEN];
+    let mut len = 0;
+    for_each_block(src, |mut block| {
+        while !block.is_empty() {
+            let n = cmp::min(HASH_BLOCK_LEN - len, block.len());
+            staged[len..len + n].copy_from_slice(&block[..n]);
+            len += n;
+            block = &block[n..];
+
+            if len == HASH_BLOCK_LEN {
+                state.write(&staged);
+                len = 0;
+            }
+        }
+        true
+    });
+
+    if len > 0 {
+        state.write(&staged[..len]);
+    }
+}
+
+/// A key for hash maps and sets which hashes and compares the decoded form of an
+/// encoded value.
+///
+/// Keys which are encoded differently but decode to the same value are equal, eg
+/// `DecodedKey("a%20b")` and `DecodedKey("a+b")`. Hashing uses
+/// [`decoded_hash`](crate::decoded_hash), so it is consistent with equality for any
+/// [`Hasher`].
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use url_decode_simd::DecodedKey;
+///
+/// let mut counts: HashMap<DecodedKey<&[u8]>, u32> = HashMap::new();
+///
+/// for query in [&b"user%20id"[..], b"user+id", b"%75ser id"].iter() {
+///     *counts.entry(DecodedKey(query)).or_insert(0) += 1;
+/// }
+///
+/// assert_eq!(Some(&3), counts.get(&DecodedKey(&b"user id"[..])));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct DecodedKey<T>(pub T);
+
+impl<T: AsRef<[u8]>> Hash for DecodedKey<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        decoded_hash(self.0.as_ref(), state)
+    }
+}
+
+impl<T: AsRef<[u8]>, U: AsRef<[u8]>> PartialEq<DecodedKey<U>> for DecodedKey<T> {
+    fn eq(&self, other: &DecodedKey<U>) -> bool {
+        decoded_values_eq(self.0.as_ref(), other.0.as_ref())
+    }
+}
+
+impl<T: AsRef<[u8]>> Eq for DecodedKey<T> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use super::{decoded_eq, decoded_starts_with, for_each_block, DecodedKey, BLOCK_LEN};
+    use crate::test_util::random_inputs;
+
+    #[test]
+    fn test_for_each_block() {
+        // Put escapes across every block boundary.
+        for shift in 0..4 {
+            let mut v = vec![b'a'; shift];
+            while v.len() < 4 * BLOCK_LEN {
+                v.extend_from_slice(b"%41%%4");
+            }
+
+            let mut expected = Vec::new();
+            crate::fallback::url_decode(&v, &mut expected);
+
+            let mut result = Vec::new();
+            assert!(for_each_block(&v, |block| {
+                result.extend_from_slice(block);
+                true
+            }));
+            assert_eq!(expected, result);
+        }
+    }
+
+    #[test]
+    fn test_for_each_block_stops() {
+        let v = vec![b'a'; 3 * BLOCK_LEN];
+        let mut blocks = 0;
+
+        assert!(!for_each_block(&v, |_| {
+            blocks += 1;
+            false
+        }));
+        assert_eq!(1, blocks);
+    }
+
+    #[test]
+    fn test_decoded_eq() {
+        assert!(decoded_eq(b"", b""));
+        assert!(decoded_eq(b"%61dmin", b"admin"));
+        assert!(decoded_eq(b"%61%64%6D%69%6E", b"admin"));
+        assert!(decoded_eq(b"a+b", b"a b"));
+        assert!(!decoded_eq(b"%61dmin", b"admi"));
+        assert!(!decoded_eq(b"%61dmin", b"admins"));
+        assert!(!decoded_eq(b"%61dmin%", b"admin"));
+        assert!(!decoded_eq(b"admin", b""));
+
+        let long = vec![b'a'; 1000];
+        let mut encoded = long.clone();
+        encoded[BLOCK_LEN - 1..BLOCK_LEN + 2].copy_from_slice(b"%61");
+        assert!(decoded_eq(&encoded, &long[..998]));
+        assert!(!decoded_eq(&encoded, &long));
+    }
+
+    #[test]
+    fn test_decoded_starts_with() {
+        assert!(decoded_starts_with(b"", b""));
+        assert!(decoded_starts_with(b"abc", b""));
+        assert!(decoded_starts_with(b"%61dmin/x", b"admin"));
+        assert!(decoded_starts_with(b"%61dmin", b"admin"));
+        assert!(!decoded_starts_with(b"%61dm", b"admin"));
+        assert!(!decoded_starts_with(b"%62dmin", b"admin"));
+
+        let mut encoded = vec![b'a'; 1000];
+        encoded[500] = b'b';
+        assert!(decoded_starts_with(&encoded, &[b'a'; 500]));
+        assert!(!decoded_starts_with(&encoded, &[b'a'; 501]));
+    }
+
+    /// A hasher which depends on how the bytes are split between calls to `write`.
+    struct SplitHasher(u64);
+
+    impl Hasher for SplitHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            self.0 = self.0.rotate_left(5) ^ bytes.len() as u64;
+            for &byte in bytes {
+                self.0 = (self.0.rotate_left(5) ^ byte as u64).wrapping_mul(0x517c_c1b7_2722_0a95);
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn hash<T: Hash>(value: &T) -> (u64, u64) {
+        let mut default = DefaultHasher::new();
+        value.hash(&mut default);
+        let mut split = SplitHasher(0);
+        value.hash(&mut split);
+        (default.finish(), split.finish())
+    }
+
+    #[test]
+    fn test_decoded_hash() {
+        // Encode the same long value in different ways, so that the blocks of input
+        // decode to different lengths.
+        let mut plain = Vec::new();
+        let mut encoded = Vec::new();
+        let mut mixed = Vec::new();
+        for i in 0..100 {
+            plain.extend_from_slice(b"key=A% ");
+            encoded.extend_from_slice(b"%6B%65%79%3D%41%25%20");
+            mixed.extend_from_slice(if i % 2 == 0 { &b"key=%41%+"[..] } else { b"k%65y%3dA%25 " });
+        }
+
+        let expected = hash(&DecodedKey(&plain));
+        assert_eq!(expected, hash(&DecodedKey(&encoded)));
+        assert_eq!(expected, hash(&DecodedKey(&mixed)));
+        assert_ne!(expected, hash(&DecodedKey(&plain[1..])));
+        assert_ne!(hash(&DecodedKey(b"")), hash(&DecodedKey(b"%")));
+    }
+
+    #[test]
+    fn test_decoded_hash_matches_url_decode() {
+        for v in random_inputs(b"%+aF0g9", 0..300, 3) {
+            let mut decoded = Vec::new();
+            crate::url_decode(&v, &mut decoded);
+            let mut expected = DefaultHasher::new();
+            decoded.hash(&mut expected);
+            let mut result = DefaultHasher::new();
+            crate::decoded_hash(&v, &mut result);
+            assert_eq!(expected.finish(), result.finish(), "input {:?}", v);
+        }
+    }
+
+    #[test]
+    fn test_decoded_key_eq() {
+        assert_eq!(DecodedKey(b"a%20b"), DecodedKey(b"a+b"));
+        assert_eq!(DecodedKey(&b""[..]), DecodedKey(b"".to_vec()));
+        assert_ne!(DecodedKey(b"a%20b"), DecodedKey(b"a%2Bb"));
+        assert_ne!(DecodedKey(&b"a"[..]), DecodedKey(&b"a%41"[..]));
+
+        let long = vec![b'a'; 5 * BLOCK_LEN];
+        let mut encoded = long.clone();
+        encoded.splice(BLOCK_LEN..BLOCK_LEN + 1, b"%61".iter().copied());
+        assert_eq!(DecodedKey(&long), DecodedKey(&encoded));
+        encoded.push(b'a');
+        assert_ne!(DecodedKey(&long), DecodedKey(&encoded));
+    }
+}
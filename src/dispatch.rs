@@ -0,0 +1,30 @@
+/// Call `$name` in the fastest backend which is compiled in.
+///
+/// The backends are chosen in the order AVX2, SSE4.1, `std::simd` and then SWAR, so
+/// each condition excludes the backends before it. The second form takes one
+/// expression per backend, for when the backends differ by more than their module.
+macro_rules! dispatch {
+    ($name:ident($($arg:expr),*)) => {
+        dispatch! {
+            avx2 => unsafe { $crate::avx2::$name($($arg),*) },
+            sse41 => unsafe { $crate::sse41::$name($($arg),*) },
+            portable => $crate::portable::$name($($arg),*),
+            swar => $crate::swar::$name($($arg),*),
+        }
+    };
+    (avx2 => $avx2:expr, sse41 => $sse41:expr, portable => $portable:expr, swar => $swar:expr $(,)?) => {{
+        #[cfg(all(target_feature = "avx2", target_feature = "popcnt"))]
+        let result = $avx2;
+
+        #[cfg(all(target_feature = "sse4.1", target_feature = "popcnt", not(target_feature = "avx2")))]
+        let result = $sse41;
+
+        #[cfg(all(feature = "portable-simd", not(all(target_feature = "sse4.1", target_feature = "popcnt"))))]
+        let result = $portable;
+
+        #[cfg(not(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd")))]
+        let result = $swar;
+
+        result
+    }};
+}
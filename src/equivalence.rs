@@ -0,0 +1,419 @@
+//! Comparing URLs under normalization without building normalized copies.
+
+use std::cmp;
+
+use crate::escapes::{Escape, EscapePositions};
+use crate::normalize::is_unreserved;
+
+/// Which differences [`urls_equivalent`](crate::urls_equivalent) ignores.
+///
+/// By default all of them are ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NormalizationOptions {
+    /// Ignore the case of the hex digits of escapes, eg `%2f` and `%2F`.
+    pub escape_case: bool,
+    /// Ignore escapes of unreserved characters, eg `%7E` and `~`.
+    pub unreserved_escapes: bool,
+    /// Ignore the case of the scheme and host, eg `HTTP://Example.com` and
+    /// `http://example.com`.
+    pub scheme_host_case: bool,
+    /// Ignore an empty port or the default port of the scheme, eg
+    /// `http://example.com:80/` and `http://example.com/`.
+    pub default_ports: bool,
+}
+
+impl Default for NormalizationOptions {
+    fn default() -> Self {
+        NormalizationOptions {
+            escape_case: true,
+            unreserved_escapes: true,
+            scheme_host_case: true,
+            default_ports: true,
+        }
+    }
+}
+
+/// Check if `a` and `b` are the same URL after normalization.
+pub(crate) fn urls_equivalent(a: &[u8], b: &[u8], options: &NormalizationOptions) -> bool {
+    if a == b {
+        return true;
+    }
+
+    let a = Parts::new(a);
+    let b = Parts::new(b);
+
+    let schemes_equal = match (a.scheme, b.scheme) {
+        (Some(x), Some(y)) if options.scheme_host_case => x.eq_ignore_ascii_case(y),
+        (x, y) => x == y,
+    };
+    if !schemes_equal {
+        return false;
+    }
+
+    match (a.authority, b.authority) {
+        (None, None) => {}
+        (Some(x), Some(y)) => {
+            let userinfo_equal = match (x.userinfo, y.userinfo) {
+                (Some(x), Some(y)) => streams_equal(x, y, options, false),
+                (x, y) => x.is_none() && y.is_none(),
+            };
+            if !userinfo_equal
+                || !streams_equal(x.host, y.host, options, options.scheme_host_case)
+                || port(a.scheme, x.port, options) != port(b.scheme, y.port, options)
+            {
+                return false;
+            }
+        }
+        _ => return false,
+    }
+
+    streams_equal(a.rest, b.rest, options, false)
+}
+
+/// A URL split into the parts which are normalized differently.
+struct Parts<'a> {
+    scheme: Option<&'a [u8]>,
+    authority: Option<Authority<'a>>,
+    /// The path, query and fragment.
+    rest: &'a [u8],
+}
+
+struct Authority<'a> {
+    userinfo: Option<&'a [u8]>,
+    host: &'a [u8],
+    port: Option<&'a [u8]>,
+}
+
+impl<'a> Parts<'a> {
+    fn new(url: &'a [u8]) -> Self {
+        let (scheme, rest) = match scheme_len(url) {
+            Some(len) => (Some(&url[..len]), &url[len + 1..]),
+            None => (None, url),
+        };
+
+        if !rest.starts_with(b"//") {
+            return Parts { scheme, authority: None, rest };
+        }
+
+        let rest = &rest[2..];
+        let end = rest.iter().position(|&byte| matches!(byte, b'/' | b'?' | b'#')).unwrap_or(rest.len());
+        let (authority, rest) = rest.split_at(end);
+
+        let (userinfo, host_port) = match authority.iter().rposition(|&byte| byte == b'@') {
+            Some(at) => (Some(&authority[..at]), &authority[at + 1..]),
+            None => (None, authority),
+        };
+
+        // The colon must come after the closing bracket of an IPv6 address.
+        let host_end = host_port.iter().rposition(|&byte| byte == b']').unwrap_or(0);
+        let (host, port) = match host_port[host_end..].iter().rposition(|&byte| byte == b':') {
+            Some(colon) => (&host_port[..host_end + colon], Some(&host_port[host_end + colon + 1..])),
+            None => (host_port, None),
+        };
+
+        Parts { scheme, authority: Some(Authority { userinfo, host, port }), rest }
+    }
+}
+
+/// Find the length of the scheme at the start of `url`, not counting the `:`.
+pub(crate) fn scheme_len(url: &[u8]) -> Option<usize> {
+    if !url.first()?.is_ascii_alphabetic() {
+        return None;
+    }
+
+    let len = url.iter().position(|&byte| !(byte.is_ascii_alphanumeric() || matches!(byte, b'+' | b'-' | b'.')))?;
+    if url[len] == b':' {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// The port to compare, which is `None` if it can be left out.
+fn port<'a>(scheme: Option<&[u8]>, port: Option<&'a [u8]>, options: &NormalizationOptions) -> Option<&'a [u8]> {
+    if !options.default_ports {
+        return port;
+    }
+
+    match (scheme, port) {
+        (_, Some(b"")) => None,
+        (Some(scheme), Some(port)) if default_port(scheme) == Some(port) => None,
+        (_, port) => port,
+    }
+}
+
+/// The default port of some common schemes.
+fn default_port(scheme: &[u8]) -> Option<&'static [u8]> {
+    let ports: &[(&[u8], &[u8])] = &[(b"http", b"80"), (b"https", b"443"), (b"ws", b"80"), (b"wss", b"443"), (b"ftp", b"21")];
+
+    ports.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(scheme))
+        .map(|&(_, port)| port)
+}
+
+/// Compare the normalized forms of `a` and `b` a piece at a time.
+///
+/// `ignore_case` only applies to plain and decoded bytes. The hex digits of escapes
+/// follow `options.escape_case`.
+fn streams_equal(a: &[u8], b: &[u8], options: &NormalizationOptions, ignore_case: bool) -> bool {
+    if a == b {
+        return true;
+    }
+
+    let mut a = Stream::new(a, options);
+    let mut b = Stream::new(b, options);
+    let mut x = Piece::default();
+    let mut y = Piece::default();
+
+    loop {
+        if x.is_empty() {
+            x = a.next().unwrap_or_default();
+        }
+        if y.is_empty() {
+            y = b.next().unwrap_or_default();
+        }
+        if x.is_empty() || y.is_empty() {
+            return x.is_empty() && y.is_empty();
+        }
+
+        let len = cmp::min(x.len(), y.len());
+        let (xs, ys) = (&x.as_slice()[..len], &y.as_slice()[..len]);
+        let equal = if ignore_case && !x.encoded && !y.encoded {
+            xs.eq_ignore_ascii_case(ys)
+        } else {
+            xs == ys
+        };
+        if !equal {
+            return false;
+        }
+
+        x.advance(len);
+        y.advance(len);
+    }
+}
+
+/// Part of the normalized form of a URL: either a run of bytes from the input or the
+/// normalized form of one escape.
+#[derive(Clone, Copy, Default)]
+struct Piece<'a> {
+    literal: &'a [u8],
+    escape: [u8; 3],
+    escape_start: usize,
+    escape_end: usize,
+    /// Whether the piece is an escape which is still encoded.
+    encoded: bool,
+}
+
+impl<'a> Piece<'a> {
+    fn literal(literal: &'a [u8]) -> Self {
+        Piece { literal, ..Piece::default() }
+    }
+
+    fn decoded(byte: u8) -> Self {
+        Piece { escape: [byte, 0, 0], escape_end: 1, ..Piece::default() }
+    }
+
+    fn encoded(escape: [u8; 3]) -> Self {
+        Piece { escape, escape_end: 3, encoded: true, ..Piece::default() }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        if self.literal.is_empty() {
+            &self.escape[self.escape_start..self.escape_end]
+        } else {
+            self.literal
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn advance(&mut self, len: usize) {
+        if self.literal.is_empty() {
+            self.escape_start += len;
+        } else {
+            self.literal = &self.literal[len..];
+        }
+    }
+}
+
+/// Produces the normalized form of a URL part as pieces.
+///
+/// The escapes are found a chunk at a time, so runs of plain bytes between them are
+/// returned whole.
+struct Stream<'a> {
+    src: &'a [u8],
+    pos: usize,
+    escapes: EscapePositions<'a>,
+    /// The next escape to normalize, once the bytes before it are returned.
+    pending: Option<Escape>,
+    options: NormalizationOptions,
+}
+
+impl<'a> Stream<'a> {
+    fn new(src: &'a [u8], options: &NormalizationOptions) -> Self {
+        Stream { src, pos: 0, escapes: EscapePositions::new(src), pending: None, options: *options }
+    }
+}
+
+impl<'a> Iterator for Stream<'a> {
+    type Item = Piece<'a>;
+
+    fn next(&mut self) -> Option<Piece<'a>> {
+        if self.pending.is_none() {
+            self.pending = self.escapes.find(|escape| escape.valid);
+        }
+
+        let escape = match self.pending {
+            Some(escape) => escape,
+            None if self.pos < self.src.len() => {
+                let literal = &self.src[self.pos..];
+                self.pos = self.src.len();
+                return Some(Piece::literal(literal));
+            }
+            None => return None,
+        };
+
+        if self.pos < escape.offset {
+            let literal = &self.src[self.pos..escape.offset];
+            self.pos = escape.offset;
+            return Some(Piece::literal(literal));
+        }
+
+        self.pending = None;
+        self.pos = escape.offset + 3;
+
+        if self.options.unreserved_escapes && is_unreserved(escape.byte) {
+            return Some(Piece::decoded(escape.byte));
+        }
+
+        let mut encoded = [0; 3];
+        encoded.copy_from_slice(&self.src[escape.offset..escape.offset + 3]);
+        if self.options.escape_case {
+            encoded.make_ascii_uppercase();
+        }
+        Some(Piece::encoded(encoded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{urls_equivalent, NormalizationOptions, Parts};
+
+    #[test]
+    fn test_parts() {
+        let parts = Parts::new(b"http://user:pw@[::1]:8080/a?b#c");
+        let authority = parts.authority.unwrap();
+        assert_eq!(Some(&b"http"[..]), parts.scheme);
+        assert_eq!(Some(&b"user:pw"[..]), authority.userinfo);
+        assert_eq!(b"[::1]", authority.host);
+        assert_eq!(Some(&b"8080"[..]), authority.port);
+        assert_eq!(b"/a?b#c", parts.rest);
+
+        let parts = Parts::new(b"mailto:a@b");
+        assert_eq!(Some(&b"mailto"[..]), parts.scheme);
+        assert!(parts.authority.is_none());
+        assert_eq!(b"a@b", parts.rest);
+
+        let parts = Parts::new(b"/a:b//c");
+        assert_eq!(None, parts.scheme);
+        assert!(parts.authority.is_none());
+
+        let authority = Parts::new(b"//[::1]").authority.unwrap();
+        assert_eq!((&b"[::1]"[..], None), (authority.host, authority.port));
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let options = NormalizationOptions::default();
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"", b""),
+            (b"http://example.com/a%2fb", b"http://example.com/a%2Fb"),
+            (b"http://example.com/%7Euser/%41", b"http://example.com/~user/A"),
+            (b"HTTP://Example.COM/", b"http://example.com/"),
+            (b"http://example.com:80/", b"http://example.com/"),
+            (b"https://example.com:443/", b"https://example.com:/"),
+            (b"http://%65xample.com/", b"http://EXAMPLE.com/"),
+            (b"/a/%62/c?%64=%65", b"/a/b/c?d=e"),
+        ];
+
+        for &(a, b) in cases {
+            assert!(urls_equivalent(a, b, &options), "{:?} {:?}", a, b);
+            assert!(urls_equivalent(b, a, &options), "{:?} {:?}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_not_equivalent() {
+        let options = NormalizationOptions::default();
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"http://example.com/a", b"http://example.com/A"),
+            (b"http://example.com/a%2Fb", b"http://example.com/a/b"),
+            (b"http://example.com/a+b", b"http://example.com/a%20b"),
+            (b"http://example.com:8080/", b"http://example.com/"),
+            (b"https://example.com:80/", b"https://example.com/"),
+            (b"http://User@example.com/", b"http://user@example.com/"),
+            (b"http://@example.com/", b"http://example.com/"),
+            (b"http://example.com/", b"https://example.com/"),
+            (b"http://example.com/", b"http:example.com/"),
+            (b"/a%", b"/a"),
+            (b"/a", b"/ab"),
+        ];
+
+        for &(a, b) in cases {
+            assert!(!urls_equivalent(a, b, &options), "{:?} {:?}", a, b);
+            assert!(!urls_equivalent(b, a, &options), "{:?} {:?}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let none = NormalizationOptions {
+            escape_case: false,
+            unreserved_escapes: false,
+            scheme_host_case: false,
+            default_ports: false,
+        };
+
+        for (a, b) in &[
+            (&b"/a%2fb"[..], &b"/a%2Fb"[..]),
+            (b"/%7E", b"/~"),
+            (b"HTTP://a/", b"http://a/"),
+            (b"http://A/", b"http://a/"),
+            (b"http://a:80/", b"http://a/"),
+        ] {
+            assert!(urls_equivalent(a, b, &NormalizationOptions::default()), "{:?} {:?}", a, b);
+            assert!(!urls_equivalent(a, b, &none), "{:?} {:?}", a, b);
+        }
+
+        // The case of the host doesn't extend to the hex digits of its escapes.
+        let host_case_only = NormalizationOptions { scheme_host_case: true, ..none };
+        assert!(urls_equivalent(b"HTTP://Ex%3Aample/", b"http://eX%3Aample/", &host_case_only));
+        assert!(!urls_equivalent(b"http://ex%3aample/", b"http://ex%3Aample/", &host_case_only));
+        assert!(!urls_equivalent(b"http://ex%3aample/", b"http://EX%3Aample/", &host_case_only));
+
+        let unreserved_only = NormalizationOptions { unreserved_escapes: true, ..none };
+        assert!(urls_equivalent(b"/%7e", b"/~", &unreserved_only));
+        assert!(!urls_equivalent(b"/%2f", b"/%2F", &unreserved_only));
+    }
+
+    #[test]
+    fn test_long() {
+        let options = NormalizationOptions::default();
+        let mut a = b"http://example.com/".to_vec();
+        let mut b = a.clone();
+        for i in 0..200 {
+            a.extend_from_slice(if i % 3 == 0 { b"%41%2f" } else { b"Aa%2F" });
+            b.extend_from_slice(if i % 3 == 0 { b"A%2F" } else { b"%41%61%2f" });
+        }
+
+        assert!(urls_equivalent(&a, &b, &options));
+        b.push(b'x');
+        assert!(!urls_equivalent(&a, &b, &options));
+    }
+}
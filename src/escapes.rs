@@ -0,0 +1,234 @@
+//! Locating the escapes in encoded input.
+
+use crate::fallback;
+
+/// A `%` in encoded input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Escape {
+    /// Offset of the `%` in the input.
+    pub offset: usize,
+    /// The decoded byte, or `%` if the escape is invalid and would be kept as is.
+    pub byte: u8,
+    /// Whether the `%` is followed by two hex digits.
+    pub valid: bool,
+}
+
+/// Iterator returned by [`escape_positions`](crate::escape_positions).
+///
+/// The input is classified a chunk at a time and the escapes are read from the bits
+/// of the masks, so runs of plain bytes are skipped quickly.
+#[derive(Clone, Debug)]
+pub struct EscapePositions<'a> {
+    src: &'a [u8],
+    /// Offset and length of the chunk the masks belong to.
+    chunk: usize,
+    chunk_len: usize,
+    /// Percent symbols in the chunk which haven't been returned yet.
+    percent: u32,
+    /// Percent symbols in the chunk which start a valid escape.
+    found: u32,
+}
+
+impl<'a> EscapePositions<'a> {
+    pub(crate) fn new(src: &'a [u8]) -> Self {
+        EscapePositions { src, chunk: 0, chunk_len: 0, percent: 0, found: 0 }
+    }
+}
+
+impl<'a> Iterator for EscapePositions<'a> {
+    type Item = Escape;
+
+    fn next(&mut self) -> Option<Escape> {
+        while self.percent == 0 {
+            if self.chunk + self.chunk_len >= self.src.len() {
+                return None;
+            }
+
+            self.chunk += self.chunk_len;
+            let (len, percent, found) = escape_masks(&self.src[self.chunk..]);
+            self.chunk_len = len;
+            self.percent = percent;
+            self.found = found;
+        }
+
+        let i = self.percent.trailing_zeros() as usize;
+        self.percent &= self.percent - 1;
+
+        let offset = self.chunk + i;
+        let valid = self.found & (1 << i) != 0;
+        let byte = if valid {
+            fallback::decode_hex(self.src[offset + 1], self.src[offset + 2]).unwrap_or(b'%')
+        } else {
+            b'%'
+        };
+
+        Some(Escape { offset, byte, valid })
+    }
+}
+
+/// Find the offset in `src` of the byte which decodes to byte `decoded_offset` of the
+/// output.
+///
+/// Each chunk produces its length in output bytes, less 2 for each escape and the
+/// hex digits carried over from the previous chunk, so whole chunks are skipped
+/// until the one containing `decoded_offset`.
+pub(crate) fn map_decoded_offset(src: &[u8], decoded_offset: usize) -> Option<usize> {
+    let mut offset = 0;
+    let mut decoded = 0;
+    // Number of bytes at the start of the chunk which are hex digits of an escape
+    // in the previous chunk.
+    let mut carry = 0;
+
+    while offset < src.len() {
+        let (len, _, found) = escape_masks(&src[offset..]);
+
+        // An escape in the last 2 bytes is decoded in this chunk.
+        let next_carry = if found & (1 << (len - 1)) != 0 {
+            2
+        } else if len > 1 && found & (1 << (len - 2)) != 0 {
+            1
+        } else {
+            0
+        };
+        let chunk_decoded = len - carry - 2 * found.count_ones() as usize + next_carry;
+
+        if decoded_offset < decoded + chunk_decoded {
+            return Some(find_in_chunk(offset, offset + carry, found, decoded_offset - decoded));
+        }
+
+        decoded += chunk_decoded;
+        carry = next_carry;
+        offset += len;
+    }
+
+    // The end of the output maps to the end of the input.
+    if decoded_offset == decoded {
+        Some(src.len())
+    } else {
+        None
+    }
+}
+
+/// Find the offset of the `n`th decoded byte in a chunk at `chunk` whose first
+/// output byte comes from `start`.
+#[inline]
+fn find_in_chunk(chunk: usize, start: usize, found: u32, n: usize) -> usize {
+    let mut pos = start;
+    let mut n = n;
+    let mut found = found;
+
+    while found != 0 {
+        let escape = chunk + found.trailing_zeros() as usize;
+        found &= found - 1;
+
+        // Plain bytes are decoded one to one.
+        if n < escape - pos {
+            return pos + n;
+        }
+        n -= escape - pos;
+
+        if n == 0 {
+            return escape;
+        }
+        n -= 1;
+        pos = escape + 3;
+    }
+
+    pos + n
+}
+
+/// Hex digits used when encoding, which are uppercase as RFC 3986 recommends.
+const HEX_UPPER: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Encode `byte` as an escape with uppercase hex digits.
+#[inline]
+pub(crate) fn encode_escape(byte: u8) -> [u8; 3] {
+    [b'%', HEX_UPPER[(byte >> 4) as usize], HEX_UPPER[(byte & 0xf) as usize]]
+}
+
+/// Find the percent symbols and valid escapes in the next chunk of `src`.
+///
+/// Returns the number of bytes classified and a mask of each with one bit per byte.
+#[inline]
+fn escape_masks(src: &[u8]) -> (usize, u32, u32) {
+    dispatch!(escape_masks(src))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{map_decoded_offset, Escape, EscapePositions};
+    use crate::test_util::random_inputs;
+
+    /// Find the escapes one byte at a time.
+    fn expected(src: &[u8]) -> Vec<Escape> {
+        let mut decoded = Vec::new();
+
+        (0..src.len()).filter(|&i| src[i] == b'%').map(|offset| {
+            decoded.clear();
+            crate::fallback::url_decode(&src[offset..(offset + 3).min(src.len())], &mut decoded);
+            let valid = decoded.len() == 1 && offset + 3 <= src.len();
+            Escape { offset, byte: decoded[0], valid }
+        }).collect()
+    }
+
+    #[test]
+    fn test_escape_positions() {
+        let v = b"%41a%%4g%2f+aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%7E%";
+        let escapes: Vec<Escape> = EscapePositions::new(v).collect();
+
+        assert_eq!(vec![
+            Escape { offset: 0, byte: b'A', valid: true },
+            Escape { offset: 4, byte: b'%', valid: false },
+            Escape { offset: 5, byte: b'%', valid: false },
+            Escape { offset: 8, byte: b'/', valid: true },
+            Escape { offset: 52, byte: b'~', valid: true },
+            Escape { offset: 55, byte: b'%', valid: false },
+        ], escapes);
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        for v in random_inputs(b"%+aF0g9\xc0", 0..100, 50) {
+            let escapes: Vec<Escape> = EscapePositions::new(&v).collect();
+            assert_eq!(expected(&v), escapes, "input {:?}", v);
+        }
+    }
+
+    /// Find the offset of every decoded byte one byte at a time.
+    fn expected_offsets(src: &[u8]) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        let mut i = 0;
+
+        while i < src.len() {
+            offsets.push(i);
+            let valid = src[i] == b'%' && expected(&src[i..]).first().map(|e| e.valid) == Some(true);
+            i += if valid { 3 } else { 1 };
+        }
+
+        offsets.push(src.len());
+        offsets
+    }
+
+    #[test]
+    fn test_map_decoded_offset() {
+        let v = b"a%41%%4g%2f+aaaaaaaaaaaaaaaaaaaa";
+        assert_eq!(Some(0), map_decoded_offset(v, 0));
+        assert_eq!(Some(1), map_decoded_offset(v, 1));
+        assert_eq!(Some(4), map_decoded_offset(v, 2));
+        assert_eq!(Some(8), map_decoded_offset(v, 6));
+        assert_eq!(Some(11), map_decoded_offset(v, 7));
+        assert_eq!(Some(v.len()), map_decoded_offset(v, 28));
+        assert_eq!(None, map_decoded_offset(v, 29));
+        assert_eq!(Some(0), map_decoded_offset(b"", 0));
+    }
+
+    #[test]
+    fn test_map_decoded_offset_matches_fallback() {
+        for v in random_inputs(b"%+aF0g9\xc0", 0..100, 20) {
+            let offsets: Vec<Option<usize>> = (0..=v.len()).map(|d| map_decoded_offset(&v, d))
+                .take_while(Option::is_some).collect();
+            let expected: Vec<Option<usize>> = expected_offsets(&v).into_iter().map(Some).collect();
+            assert_eq!(expected, offsets, "input {:?}", v);
+        }
+    }
+}
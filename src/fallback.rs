@@ -1,37 +1,24 @@
-//! This is a slightly modified version of the
-//! [url crate](https://crates.io/crates/url)'s decode implementation.
+//! A scalar implementation of URL decode.
+//!
+//! It jumps between `%` and `+` symbols using `memchr2` and copies the runs of
+//! plain bytes in between, so no intermediate buffers are allocated.
 
-use std::slice;
-use std::borrow::Cow;
+use memchr::memchr2;
 
-#[inline]
-fn percent_decode(input: &[u8]) -> PercentDecode {
-    PercentDecode {
-        bytes: input.iter(),
-    }
-}
+use crate::validation::{self, ValidationError, ValidationErrorKind, ValidationRules};
 
-/// Replace b'+' with b' '
-fn replace_plus(input: &[u8]) -> Cow<[u8]> {
-    match input.iter().position(|&b| b == b'+') {
-        None => Cow::Borrowed(input),
-        Some(first_position) => {
-            let mut replaced = input.to_owned();
-            replaced[first_position] = b' ';
-            for byte in &mut replaced[first_position + 1..] {
-                if *byte == b'+' {
-                    *byte = b' ';
-                }
-            }
-            Cow::Owned(replaced)
-        }
-    }
+/// Decode a pair of hex digits into a byte.
+#[inline]
+pub(crate) fn decode_hex(high: u8, low: u8) -> Option<u8> {
+    let h = char::from(high).to_digit(16)?;
+    let l = char::from(low).to_digit(16)?;
+    Some(h as u8 * 0x10 + l as u8)
 }
 
-/// Decode a URL-encoded value into the given Vector.
+/// Decode a URL-encoded value and append the result to the given Vector.
 ///
-/// This is a non-SIMD implementation used as a fallback if the required SIMD instructions
-/// are not supported.
+/// This is a non-SIMD implementation which is simple enough to use as a reference
+/// for the optimised implementations.
 ///
 /// # Examples
 ///
@@ -45,102 +32,59 @@ fn replace_plus(input: &[u8]) -> Cow<[u8]> {
 /// assert_eq!(b"Hello world!", &output[..]);
 /// ```
 pub fn url_decode(src: &[u8], dst: &mut Vec<u8>) {
-    let src = replace_plus(src);
-    match percent_decode(&src).if_any() {
-        Some(vec) => *dst = vec,
-        None => *dst = src.to_vec(),
-    };
-}
+    let mut src = src;
 
-/// Decode a URL-encoded value and extend the given vector with the result.
-///
-/// This is used if the length of the input data to the SIMD-implementations is not divisible
-/// by the width of the SIMD instructions.
-///
-/// # Examples
-///
-/// ```
-/// let input = b"%20world%21";
-/// let mut output = b"Hello".to_vec();
-///
-/// decode_extend(input, &mut output);
-/// assert_eq!(b"Hello world!", &output);
-/// ```
-#[cfg(all(any(target_feature = "avx2", target_feature = "sse4.1"), target_feature = "popcnt"))]
-pub (crate) fn decode_extend(src: &[u8], dst: &mut Vec<u8>) {
-    let src = replace_plus(src);
-    match percent_decode(&src).if_any() {
-        Some(vec) => dst.extend_from_slice(&vec),
-        None => dst.extend_from_slice(&src),
-    };
-}
-
-/// The return type of [`percent_decode`].
-#[derive(Clone, Debug)]
-struct PercentDecode<'a> {
-    bytes: slice::Iter<'a, u8>,
-}
+    dst.reserve(src.len());
 
-fn after_percent_sign(iter: &mut slice::Iter<u8>) -> Option<u8> {
-    let mut cloned_iter = iter.clone();
-    let h = char::from(*cloned_iter.next()?).to_digit(16)?;
-    let l = char::from(*cloned_iter.next()?).to_digit(16)?;
-    *iter = cloned_iter;
-    Some(h as u8 * 0x10 + l as u8)
-}
+    while let Some(i) = memchr2(b'%', b'+', src) {
+        dst.extend_from_slice(&src[..i]);
 
-impl<'a> Iterator for PercentDecode<'a> {
-    type Item = u8;
+        if src[i] == b'+' {
+            dst.push(b' ');
+            src = &src[i + 1..];
+            continue;
+        }
 
-    fn next(&mut self) -> Option<u8> {
-        self.bytes.next().map(|&byte| {
-            if byte == b'%' {
-                after_percent_sign(&mut self.bytes).unwrap_or(byte)
-            } else {
-                byte
+        match src.get(i + 1..i + 3).and_then(|hex| decode_hex(hex[0], hex[1])) {
+            Some(byte) => {
+                dst.push(byte);
+                src = &src[i + 3..];
+            }
+            None => {
+                dst.push(b'%');
+                src = &src[i + 1..];
             }
-        })
+        }
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        let bytes = self.bytes.len();
-        (bytes / 3, Some(bytes))
-    }
+    dst.extend_from_slice(src);
 }
 
-impl<'a> From<PercentDecode<'a>> for Cow<'a, [u8]> {
-    fn from(iter: PercentDecode<'a>) -> Self {
-        match iter.if_any() {
-            Some(vec) => Cow::Owned(vec),
-            None => Cow::Borrowed(iter.bytes.as_slice()),
+/// Check that every `%` in `src` starts a valid escape, and optionally that every
+/// byte is allowed by RFC 3986.
+///
+/// Returns the first offending byte.
+#[cfg_attr(not(feature = "benchmark"), allow(dead_code))]
+pub fn validate(src: &[u8], rules: &ValidationRules) -> Result<(), ValidationError> {
+    for (i, &byte) in src.iter().enumerate() {
+        if byte == b'%' && src.get(i + 1..i + 3).and_then(|hex| decode_hex(hex[0], hex[1])).is_none() {
+            return Err(ValidationError::new(i, ValidationErrorKind::InvalidEscape));
         }
-    }
-}
 
-impl<'a> PercentDecode<'a> {
-    /// If the percent-decoding is different from the input, return it as a new bytes vector.
-    fn if_any(&self) -> Option<Vec<u8>> {
-        let mut bytes_iter = self.bytes.clone();
-        while bytes_iter.any(|&b| b == b'%') {
-            if let Some(decoded_byte) = after_percent_sign(&mut bytes_iter) {
-                let initial_bytes = self.bytes.as_slice();
-                let unchanged_bytes_len = initial_bytes.len() - bytes_iter.len() - 3;
-                let mut decoded = initial_bytes[..unchanged_bytes_len].to_owned();
-                decoded.push(decoded_byte);
-                decoded.extend(PercentDecode { bytes: bytes_iter });
-                return Some(decoded);
-            }
+        if rules.rfc3986_chars_only && !validation::is_allowed(byte) {
+            return Err(ValidationError::new(i, ValidationErrorKind::DisallowedByte));
         }
-        // Nothing to decode
-        None
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     #![allow(non_snake_case)]
 
-    use super::url_decode;
+    use super::{url_decode, validate};
+    use crate::validation::{ValidationErrorKind, ValidationRules};
 
     #[test]
     fn url_decode_space() {
@@ -292,4 +236,28 @@ mod tests {
         url_decode(v, &mut result);
         assert_eq!(b"\xCF%%sA\x00`A%5%%6%6\xEF", &result[..]);
     }
+
+    #[test]
+    fn test_append() {
+        let mut result = b"abc".to_vec();
+
+        let v = b"%41a%42b12345678%43";
+        url_decode(v, &mut result);
+        assert_eq!(b"abcAaBb12345678C", &result[..]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let rules = ValidationRules::default();
+        let strict = ValidationRules { rfc3986_chars_only: true };
+
+        assert_eq!(Ok(()), validate(b"/a+b/%41?c=d#e", &strict));
+        assert_eq!(Ok(()), validate(b"a b", &rules));
+
+        let error = validate(b"%41%4", &rules).unwrap_err();
+        assert_eq!((3, ValidationErrorKind::InvalidEscape), (error.offset(), error.kind()));
+
+        let error = validate(b"a b%", &strict).unwrap_err();
+        assert_eq!((1, ValidationErrorKind::DisallowedByte), (error.offset(), error.kind()));
+    }
 }
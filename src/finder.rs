@@ -0,0 +1,241 @@
+//! Searching encoded input for a decoded needle.
+
+use std::ops::Range;
+
+use memchr::memchr3;
+
+use crate::fallback::decode_hex;
+
+/// Searches encoded haystacks for a needle in its decoded form.
+///
+/// The needle matches however its bytes are encoded, eg `<` matches `<`, `%3C` and
+/// `%3c`, and a space also matches `+`. Matches are returned as ranges of the
+/// encoded haystack and always start and end on whole escapes.
+///
+/// Candidates are found with `memchr`, which uses SIMD where available, looking for
+/// the first byte of the needle and the `%` or `+` that may encode it. Each candidate
+/// is then verified by decoding the haystack from that position.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::DecodedFinder;
+///
+/// let finder = DecodedFinder::new(b"<script>");
+/// let haystack = b"GET /?q=%3cscript%3Ealert(1)";
+///
+/// assert_eq!(Some(8..20), finder.find(haystack));
+/// ```
+#[derive(Clone, Debug)]
+pub struct DecodedFinder {
+    needle: Vec<u8>,
+}
+
+impl DecodedFinder {
+    /// Create a finder for `needle`, which is not encoded.
+    pub fn new(needle: &[u8]) -> Self {
+        DecodedFinder { needle: needle.to_vec() }
+    }
+
+    /// The needle this finder searches for.
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    /// Find the first match in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<Range<usize>> {
+        self.find_from(haystack, 0)
+    }
+
+    /// Iterate over the non-overlapping matches in `haystack`.
+    pub fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> FindIter<'a> {
+        FindIter { finder: self, haystack, pos: 0 }
+    }
+
+    /// Find the first match in `haystack` starting at or after `pos`.
+    fn find_from(&self, haystack: &[u8], pos: usize) -> Option<Range<usize>> {
+        let first = match self.needle.first() {
+            Some(&first) => first,
+            None => return if pos <= haystack.len() { Some(pos..pos) } else { None },
+        };
+
+        // A `+` in the haystack is a space, so it never matches itself.
+        let literal = if first == b'+' { b'%' } else { first };
+        let plus = if first == b' ' { b'+' } else { b'%' };
+
+        let mut pos = pos;
+        while pos < haystack.len() {
+            let start = pos + memchr3(literal, b'%', plus, &haystack[pos..])?;
+
+            if is_boundary(haystack, start) {
+                if let Some(end) = self.match_at(haystack, start) {
+                    return Some(start..end);
+                }
+            }
+            pos = start + 1;
+        }
+
+        None
+    }
+
+    /// Decode `haystack` from `start` and compare it to the needle.
+    ///
+    /// Returns the end of the match.
+    #[inline]
+    fn match_at(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let mut pos = start;
+
+        for &byte in &self.needle {
+            let (decoded, len) = decode_one(haystack, pos)?;
+            if decoded != byte {
+                return None;
+            }
+            pos += len;
+        }
+
+        Some(pos)
+    }
+}
+
+/// Iterator returned by [`DecodedFinder::find_iter`].
+#[derive(Clone, Debug)]
+pub struct FindIter<'a> {
+    finder: &'a DecodedFinder,
+    haystack: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for FindIter<'a> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        let found = self.finder.find_from(self.haystack, self.pos)?;
+
+        // Step past an empty match so that it isn't found again.
+        self.pos = if found.is_empty() {
+            match decode_one(self.haystack, found.end) {
+                Some((_, len)) => found.end + len,
+                None => self.haystack.len() + 1,
+            }
+        } else {
+            found.end
+        };
+
+        Some(found)
+    }
+}
+
+/// Decode the byte at `pos`, returning it and the number of bytes of input used.
+#[inline]
+fn decode_one(src: &[u8], pos: usize) -> Option<(u8, usize)> {
+    match *src.get(pos)? {
+        b'+' => Some((b' ', 1)),
+        b'%' => match escape_at(src, pos) {
+            Some(byte) => Some((byte, 3)),
+            None => Some((b'%', 1)),
+        },
+        byte => Some((byte, 1)),
+    }
+}
+
+/// Decode the escape at `pos` if it is valid.
+#[inline]
+fn escape_at(src: &[u8], pos: usize) -> Option<u8> {
+    match src.get(pos..pos + 3)? {
+        [b'%', high, low] => decode_hex(*high, *low),
+        _ => None,
+    }
+}
+
+/// Check that `pos` is not within the hex digits of an escape.
+#[inline]
+fn is_boundary(src: &[u8], pos: usize) -> bool {
+    let in_escape = |start: Option<usize>| start.and_then(|start| escape_at(src, start)).is_some();
+    !in_escape(pos.checked_sub(1)) && !in_escape(pos.checked_sub(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use super::{decode_one, DecodedFinder};
+    use crate::test_util::random_inputs;
+
+    /// Find the matches by decoding every position.
+    fn expected(needle: &[u8], haystack: &[u8]) -> Vec<Range<usize>> {
+        let mut boundaries = Vec::new();
+        let mut pos = 0;
+        while let Some((_, len)) = decode_one(haystack, pos) {
+            boundaries.push(pos);
+            pos += len;
+        }
+
+        let mut matches = Vec::new();
+        let mut next = 0;
+        for (i, &start) in boundaries.iter().enumerate() {
+            if start < next || i + needle.len() > boundaries.len() {
+                continue;
+            }
+
+            let end = boundaries.get(i + needle.len()).copied().unwrap_or(haystack.len());
+            let mut decoded = Vec::new();
+            crate::fallback::url_decode(&haystack[start..end], &mut decoded);
+
+            if decoded == needle {
+                matches.push(start..end);
+                next = end;
+            }
+        }
+        matches
+    }
+
+    #[test]
+    fn test_find() {
+        let finder = DecodedFinder::new(b"<script>");
+
+        assert_eq!(Some(0..8), finder.find(b"<script>"));
+        assert_eq!(Some(2..14), finder.find(b"a=%3Cscript%3e%3E%3e"));
+        assert_eq!(Some(1..15), finder.find(b"%%3C%73cript%3E%3E"));
+        assert_eq!(None, finder.find(b"%3Cscript"));
+        assert_eq!(None, finder.find(b""));
+    }
+
+    #[test]
+    fn test_find_inside_escape() {
+        // `%2541` decodes to `%41`, not `A`.
+        let finder = DecodedFinder::new(b"A");
+        assert_eq!(None, finder.find(b"%2541"));
+        assert_eq!(Some(1..4), finder.find(b"%%41"));
+
+        let finder = DecodedFinder::new(b"41");
+        assert_eq!(None, finder.find(b"%41"));
+        assert_eq!(Some(3..5), finder.find(b"%2541"));
+    }
+
+    #[test]
+    fn test_find_space_and_plus() {
+        let finder = DecodedFinder::new(b"a b");
+        assert_eq!(vec![0..3, 3..8, 8..11], finder.find_iter(b"a+ba%20ba b").collect::<Vec<_>>());
+
+        let finder = DecodedFinder::new(b"+");
+        assert_eq!(vec![1..4], finder.find_iter(b"+%2B").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_find_iter_empty_needle() {
+        let finder = DecodedFinder::new(b"");
+        assert_eq!(vec![0..0, 3..3, 4..4], finder.find_iter(b"%41a").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        let needles: &[&[u8]] = &[b"a", b" a", b"%", b"\xaf", b"\xf0a", b"F0", b"%a"];
+        for v in random_inputs(b"%+ aF0g9", 0..50, 50) {
+            for needle in needles {
+                let finder = DecodedFinder::new(needle);
+                let matches: Vec<Range<usize>> = finder.find_iter(&v).collect();
+                assert_eq!(expected(needle, &v), matches, "needle {:?} input {:?}", needle, v);
+            }
+        }
+    }
+}
@@ -1,27 +1,101 @@
+#![cfg_attr(feature = "portable-simd", feature(portable_simd))]
+
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
 #[macro_use]
 mod debug;
-#[cfg(all(any(target_feature = "sse4.1"), target_feature = "popcnt"))]
+#[macro_use]
+mod dispatch;
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
 mod shuffle_mask;
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+mod simd;
+#[cfg(test)]
+mod test_util;
+
+mod canonical;
+mod chunked;
+mod display;
+mod equivalence;
+mod escapes;
+mod finder;
+mod mode;
+mod normalize;
+mod policy;
+mod report;
+mod safe_path;
+mod segments;
+mod selective;
+mod validation;
 
 #[cfg(feature = "benchmark")]
 pub mod fallback;
 #[cfg(feature = "benchmark")]
+#[cfg(all(target_feature = "avx2", target_feature = "popcnt"))]
+pub mod avx2;
+#[cfg(feature = "benchmark")]
 #[cfg(all(target_feature = "sse4.1", target_feature = "popcnt"))]
+#[cfg_attr(target_feature = "avx2", allow(dead_code))]
 pub mod sse41;
+#[cfg(feature = "benchmark")]
+#[cfg(feature = "portable-simd")]
+#[cfg_attr(all(target_feature = "sse4.1", target_feature = "popcnt"), allow(dead_code))]
+pub mod portable;
+#[cfg(feature = "benchmark")]
+#[cfg_attr(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"), allow(dead_code))]
+pub mod swar;
 
 #[cfg(not(feature = "benchmark"))]
 mod fallback;
 #[cfg(not(feature = "benchmark"))]
+#[cfg(all(target_feature = "avx2", target_feature = "popcnt"))]
+mod avx2;
+#[cfg(not(feature = "benchmark"))]
 #[cfg(all(target_feature = "sse4.1", target_feature = "popcnt"))]
+#[cfg_attr(target_feature = "avx2", allow(dead_code))]
 mod sse41;
-
 #[cfg(not(feature = "benchmark"))]
+#[cfg(feature = "portable-simd")]
+#[cfg_attr(all(target_feature = "sse4.1", target_feature = "popcnt"), allow(dead_code))]
+mod portable;
+#[cfg(not(feature = "benchmark"))]
+#[cfg_attr(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"), allow(dead_code))]
+mod swar;
+
 pub use fallback::url_decode as fallback_decode;
+pub use canonical::Canonical;
+pub use chunked::DecodedKey;
+pub use equivalence::NormalizationOptions;
+pub use escapes::{Escape, EscapePositions};
+pub use finder::{DecodedFinder, FindIter};
+pub use policy::{ControlError, ControlPolicies, ControlPolicy};
+pub use report::DecodeReport;
+pub use safe_path::PathError;
+pub use segments::PathSegments;
+pub use selective::KeepEncodedSet;
+pub use validation::{ValidationError, ValidationErrorKind, ValidationRules};
+
+/// Which characters are decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Only `%XX` escapes are decoded, as in a URL path.
+    Path,
+    /// `%XX` escapes are decoded and `+` is replaced with a space, as in
+    /// `application/x-www-form-urlencoded` data.
+    Form,
+}
 
-/// Decode a URL-encoded value into the given Vector.
+/// Decode a URL-encoded value and append the result to the given Vector.
+///
+/// Any existing contents of `dst` are kept. Every implementation behaves the same
+/// way regardless of which CPU extensions are compiled in. See [`url_decode_replace`]
+/// to overwrite `dst` instead.
 ///
-/// If compiled with support for SSE4.1 and POPCNT extensions, and the input is
-/// at least 16 bytes, it will use an optimised implementation.
+/// If compiled with support for AVX2 or SSE4.1, and POPCNT extensions, it will use
+/// an optimised implementation. Otherwise, if the `portable-simd` feature is enabled
+/// it uses `std::simd`, else a portable implementation which processes 8 bytes at
+/// a time.
 ///
 /// # Examples
 ///
@@ -35,19 +109,472 @@ pub use fallback::url_decode as fallback_decode;
 /// assert_eq!(b"Hello world!", &output[..]);
 /// ```
 #[inline]
-#[allow(unreachable_code)]
 pub fn url_decode(src: &[u8], dst: &mut Vec<u8>) {
-    #[cfg(all(target_feature = "sse4.1", target_feature = "popcnt"))]
-    return unsafe { sse41::url_decode(src, dst) };
+    dispatch!(url_decode(src, dst))
+}
+
+/// Count the bytes that [`url_decode`] would output for `src` without decoding it.
+///
+/// This can be used to allocate a buffer of exactly the right size. Every valid
+/// escape is counted using the same classification as the decoder.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::{decoded_len, url_decode};
+///
+/// let input = b"Hello%20world%21";
+/// let mut output = Vec::with_capacity(decoded_len(input));
+///
+/// url_decode(input, &mut output);
+/// assert_eq!(12, output.len());
+/// ```
+#[inline]
+pub fn decoded_len(src: &[u8]) -> usize {
+    dispatch!(decoded_len(src))
+}
+
+/// Check if decoding `src` in the given mode would change it.
+///
+/// This is true if `src` contains a valid `%XX` escape, or a `+` in [`Mode::Form`].
+/// The scan stops at the first one found, so it is cheap to call before deciding
+/// whether to decode at all.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::{needs_decoding, Mode};
+///
+/// assert!(!needs_decoding(b"/plain/path", Mode::Path));
+/// assert!(!needs_decoding(b"/a+b/100%", Mode::Path));
+/// assert!(needs_decoding(b"/a+b/100%", Mode::Form));
+/// assert!(needs_decoding(b"/hello%20world", Mode::Path));
+/// ```
+#[inline]
+pub fn needs_decoding(src: &[u8], mode: Mode) -> bool {
+    dispatch!(needs_decoding(src, mode))
+}
 
-    fallback::url_decode(src, dst);
+/// Check that `src` is well-formed percent-encoding without decoding it.
+///
+/// Every `%` must be followed by two hex digits. If [`ValidationRules::rfc3986_chars_only`]
+/// is set, every other byte must also be allowed unescaped by RFC 3986. The error
+/// reports the first offending byte.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::{validate, ValidationErrorKind, ValidationRules};
+///
+/// let rules = ValidationRules::default();
+/// assert!(validate(b"/hello%20world", &rules).is_ok());
+///
+/// let error = validate(b"/100%", &rules).unwrap_err();
+/// assert_eq!(4, error.offset());
+/// assert_eq!(ValidationErrorKind::InvalidEscape, error.kind());
+///
+/// let rules = ValidationRules { rfc3986_chars_only: true };
+/// let error = validate(b"/hello world", &rules).unwrap_err();
+/// assert_eq!(6, error.offset());
+/// assert_eq!(ValidationErrorKind::DisallowedByte, error.kind());
+/// ```
+#[inline]
+pub fn validate(src: &[u8], rules: &ValidationRules) -> Result<(), ValidationError> {
+    dispatch!(validate(src, rules))
+}
+
+/// Iterate over every `%` in `src`, whether or not it starts a valid escape.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::{escape_positions, Escape};
+///
+/// let escapes: Vec<Escape> = escape_positions(b"100%25 %zz").collect();
+///
+/// assert_eq!(vec![
+///     Escape { offset: 3, byte: b'%', valid: true },
+///     Escape { offset: 7, byte: b'%', valid: false },
+/// ], escapes);
+/// ```
+#[inline]
+pub fn escape_positions(src: &[u8]) -> EscapePositions<'_> {
+    EscapePositions::new(src)
+}
+
+/// Find the offset in `src` of the byte at `decoded_offset` in the output of
+/// [`url_decode`].
+///
+/// A decoded byte from an escape maps to the offset of its `%`. The end of the
+/// output maps to the end of the input, and offsets past that return `None`. This
+/// can be used to point at the original URL when reporting an error in a decoded
+/// value.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::map_decoded_offset;
+///
+/// let input = b"key=%7B%22a%22:1,}";
+///
+/// // The decoded value is `key={"a":1,}` and the error is at the `}`.
+/// assert_eq!(Some(17), map_decoded_offset(input, 11));
+/// assert_eq!(Some(7), map_decoded_offset(input, 5));
+/// ```
+#[inline]
+pub fn map_decoded_offset(src: &[u8], decoded_offset: usize) -> Option<usize> {
+    escapes::map_decoded_offset(src, decoded_offset)
+}
+
+/// Check if `encoded` decodes to `expected` without allocating.
+///
+/// The input is decoded one vector at a time into a buffer on the stack and compared
+/// as it goes, so decoding stops at the first vector with a mismatch. Decoding is the same
+/// as [`url_decode`], including `+` as a space.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::decoded_eq;
+///
+/// assert!(decoded_eq(b"%61dmin", b"admin"));
+/// assert!(!decoded_eq(b"%61dmin%2F", b"admin"));
+/// ```
+#[inline]
+pub fn decoded_eq(encoded: &[u8], expected: &[u8]) -> bool {
+    chunked::decoded_eq(encoded, expected)
+}
+
+/// Check if `encoded` decodes to a value starting with `prefix` without allocating.
+///
+/// Decoding stops as soon as the prefix has been matched or a mismatch is found.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::decoded_starts_with;
+///
+/// assert!(decoded_starts_with(b"%2Fadmin%2Fusers", b"/admin/"));
+/// assert!(!decoded_starts_with(b"%2Fpublic", b"/admin/"));
+/// ```
+#[inline]
+pub fn decoded_starts_with(encoded: &[u8], prefix: &[u8]) -> bool {
+    chunked::decoded_starts_with(encoded, prefix)
+}
+
+/// Hash the decoded form of `src` without allocating.
+///
+/// The decoded length is written to `state` with [`Hasher::write_usize`], followed
+/// by the decoded bytes in blocks of a fixed size. The calls to `state` only depend
+/// on the decoded value, so values which decode to the same bytes have the same hash
+/// with any [`Hasher`], however they are encoded.
+///
+/// This is the same as hashing the output of [`url_decode`] as a `[u8]` or `Vec<u8>`
+/// with hashers which don't depend on how the bytes are split between calls to
+/// [`Hasher::write`], such as the SipHash based `DefaultHasher` used by `HashMap`.
+/// With other hashers, a `HashMap` should use [`DecodedKey`], which hashes with this
+/// function, for both its keys and lookups.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::hash::Hasher;
+/// use url_decode_simd::decoded_hash;
+///
+/// let mut a = DefaultHasher::new();
+/// decoded_hash(b"hello world", &mut a);
+///
+/// let mut b = DefaultHasher::new();
+/// decoded_hash(b"hello%20world", &mut b);
+///
+/// assert_eq!(a.finish(), b.finish());
+/// ```
+#[inline]
+pub fn decoded_hash<H: Hasher>(src: &[u8], state: &mut H) {
+    chunked::decoded_hash(src, state)
+}
+
+/// Decode `src` repeatedly until it no longer changes, up to `max_passes` times.
+///
+/// Values which have been encoded more than once, eg `%252e%252e` for `..`, are
+/// decoded fully so that filters see the same value however many times it was
+/// encoded. The result records how many passes decoded an escape, so multiple
+/// encoding can be rejected instead.
+///
+/// The first pass is the same as [`url_decode`], including `+` as a space. Later
+/// passes only decode escapes, so a `+` from `%2B` is kept.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::canonicalize;
+///
+/// let result = canonicalize(b"%252e%252e%252fetc", 4);
+///
+/// assert_eq!(b"../etc", &result.value[..]);
+/// assert_eq!(2, result.passes);
+/// assert!(result.complete);
+/// assert!(result.is_multiply_encoded());
+/// ```
+#[inline]
+pub fn canonicalize(src: &[u8], max_passes: usize) -> Canonical {
+    canonical::canonicalize(src, max_passes)
+}
+
+/// Decode a URL-encoded value and append the result to the given Vector, reporting
+/// suspicious escapes.
+///
+/// The output is the same as [`url_decode`]. The escapes are inspected as each chunk
+/// is decoded, so this costs little more than decoding alone.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::url_decode_with_report;
+///
+/// let mut output = Vec::new();
+/// let report = url_decode_with_report(b"/files/%2e%2e%2fetc%00", &mut output);
+///
+/// assert_eq!(b"/files/../etc\0", &output[..]);
+/// assert!(report.encoded_nul);
+/// assert!(report.encoded_separator);
+/// assert!(!report.encoded_dot_segment);
+/// assert!(!report.is_clean());
+/// ```
+#[inline]
+pub fn url_decode_with_report(src: &[u8], dst: &mut Vec<u8>) -> DecodeReport {
+    report::url_decode_with_report(src, dst)
+}
+
+/// Decode a URL-encoded value and append the result to the given Vector, applying
+/// a policy to escaped NUL and control characters.
+///
+/// The policies are applied as each chunk is decoded, not by scanning the output
+/// afterwards. If an escape is rejected, the Vector is left as it was and the first
+/// rejected escape is returned.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::{url_decode_with_policy, ControlPolicies, ControlPolicy};
+///
+/// let policies = ControlPolicies {
+///     nul: ControlPolicy::Reject,
+///     cr_lf: ControlPolicy::KeepEncoded,
+///     other_c0: ControlPolicy::Replace(b'?'),
+/// };
+///
+/// let mut output = Vec::new();
+/// url_decode_with_policy(b"a%0D%0Ab%07", &mut output, &policies).unwrap();
+/// assert_eq!(b"a%0D%0Ab?", &output[..]);
+///
+/// let error = url_decode_with_policy(b"file%00.txt", &mut output, &policies).unwrap_err();
+/// assert_eq!(4, error.offset());
+/// assert_eq!(b"a%0D%0Ab?", &output[..]);
+/// ```
+#[inline]
+pub fn url_decode_with_policy(src: &[u8], dst: &mut Vec<u8>, policies: &ControlPolicies) -> Result<(), ControlError> {
+    policy::url_decode_with_policy(src, dst, policies)
+}
+
+/// Decode a URL-encoded value and append the result to the given Vector, keeping the
+/// escapes of the bytes in `keep` encoded.
+///
+/// Kept escapes are copied as they are, including the case of their hex digits. A
+/// `+` is still decoded to a space.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::{url_decode_selective, KeepEncodedSet};
+///
+/// let keep = KeepEncodedSet::new(b"/?%");
+/// let mut output = Vec::new();
+///
+/// url_decode_selective(b"/a%2Fb/%41%3f%25", &mut output, &keep);
+/// assert_eq!(b"/a%2Fb/A%3f%25", &output[..]);
+/// ```
+#[inline]
+pub fn url_decode_selective(src: &[u8], dst: &mut Vec<u8>, keep: &KeepEncodedSet) {
+    selective::url_decode_selective(src, dst, keep)
+}
+
+/// Normalize the percent-encoding of a URL and append the result to the given Vector.
+///
+/// As described in RFC 3986 section 6.2.2, escapes of unreserved characters
+/// (`A-Z a-z 0-9 - . _ ~`) are decoded and the hex digits of the other escapes are
+/// made uppercase. Everything else, including `+` and invalid escapes, is copied as it
+/// is.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::normalize_percent_encoding;
+///
+/// let mut output = Vec::new();
+///
+/// normalize_percent_encoding(b"/%7euser/a%2fb?q=%e2%82%ac", &mut output);
+/// assert_eq!(b"/~user/a%2Fb?q=%E2%82%AC", &output[..]);
+/// ```
+#[inline]
+pub fn normalize_percent_encoding(src: &[u8], dst: &mut Vec<u8>) {
+    normalize::normalize_percent_encoding(src, dst)
+}
+
+/// Check if the percent-encoding of a URL is already normalized, so that
+/// [`normalize_percent_encoding`] would leave it unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::is_percent_encoding_normalized;
+///
+/// assert!(is_percent_encoding_normalized(b"/a%2Fb"));
+/// assert!(!is_percent_encoding_normalized(b"/a%2fb"));
+/// assert!(!is_percent_encoding_normalized(b"/%41"));
+/// ```
+#[inline]
+pub fn is_percent_encoding_normalized(src: &[u8]) -> bool {
+    normalize::is_percent_encoding_normalized(src)
+}
+
+/// Check if two URLs are the same after normalization.
+///
+/// The differences ignored are chosen with `options`. The URLs are compared a piece
+/// at a time as their escapes are found, without building normalized copies.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::{urls_equivalent, NormalizationOptions};
+///
+/// let options = NormalizationOptions::default();
+///
+/// assert!(urls_equivalent(b"HTTP://Example.com:80/%7euser/a%2fb", b"http://example.com/~user/a%2Fb", &options));
+/// assert!(!urls_equivalent(b"http://example.com/a%2Fb", b"http://example.com/a/b", &options));
+/// ```
+#[inline]
+pub fn urls_equivalent(a: &[u8], b: &[u8], options: &NormalizationOptions) -> bool {
+    equivalence::urls_equivalent(a, b, options)
+}
+
+/// Split a path into segments and decode each one.
+///
+/// The path is split on literal `/` before decoding, so an escaped `/` stays inside
+/// its segment. A leading `/` is skipped. Unlike [`url_decode`], a `+` is kept as it
+/// is. Segments without escapes are borrowed from the path.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::decode_path_segments;
+///
+/// let segments: Vec<_> = decode_path_segments(b"/files/a%2Fb/c+d").collect();
+///
+/// assert_eq!(3, segments.len());
+/// assert_eq!(b"files", &segments[0][..]);
+/// assert_eq!(b"a/b", &segments[1][..]);
+/// assert_eq!(b"c+d", &segments[2][..]);
+/// ```
+#[inline]
+pub fn decode_path_segments(path: &[u8]) -> PathSegments<'_> {
+    PathSegments::new(path)
+}
+
+/// Decode a request path and join it to `root`, for serving files.
+///
+/// The path is split into segments with [`decode_path_segments`] and `.` and `..`
+/// segments are removed as in RFC 3986 section 5.2.4. Paths with a NUL, a backslash or
+/// an escaped `/` in a segment, or with a `..` which would leave `root`, are rejected.
+/// On Unix the segments are used as they are, without requiring UTF-8.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use url_decode_simd::{decode_to_safe_path, PathError};
+///
+/// let root = Path::new("/srv/www");
+///
+/// assert_eq!(Ok(PathBuf::from("/srv/www/b/c d.txt")), decode_to_safe_path(b"/a/../b/c%20d.txt", root));
+/// assert_eq!(Err(PathError::EscapesRoot), decode_to_safe_path(b"/%2e%2e/etc/passwd", root));
+/// assert_eq!(Err(PathError::Nul), decode_to_safe_path(b"/index.html%00.txt", root));
+/// ```
+#[inline]
+pub fn decode_to_safe_path(path: &[u8], root: &Path) -> Result<PathBuf, PathError> {
+    safe_path::decode_to_safe_path(path, root)
+}
+
+/// Decode a whole URL into a readable form for UIs and logs, keeping its structure.
+///
+/// Each component is decoded with its own rules. Escapes of characters which would
+/// change the meaning of the component stay encoded, eg `%2F` in the path and `%26`
+/// in the query. A `+` is decoded to a space only in the query. Escapes of `%` and
+/// control characters always stay encoded. Bytes which are not valid UTF-8 are
+/// escaped, as are unescaped C0 controls and DEL, C1 controls, bidi controls and the
+/// line and paragraph separators, which could change how the text is shown.
+///
+/// The result is meant to be read, not parsed again.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::decode_url_for_display;
+///
+/// let url = b"https://example.com/caf%C3%A9/a%2Fb?q=fish+%26+chips%0A#top";
+///
+/// assert_eq!("https://example.com/café/a%2Fb?q=fish %26 chips%0A#top", decode_url_for_display(url));
+/// ```
+#[inline]
+pub fn decode_url_for_display(url: &[u8]) -> String {
+    display::decode_url_for_display(url)
+}
+
+/// Decode a URL-encoded value and append the result to the given Vector.
+///
+/// This is the same as [`url_decode`] but makes the intent explicit.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::url_decode_append;
+///
+/// let mut output = b"Hello".to_vec();
+///
+/// url_decode_append(b"%20world%21", &mut output);
+/// assert_eq!(b"Hello world!", &output[..]);
+/// ```
+#[inline]
+pub fn url_decode_append(src: &[u8], dst: &mut Vec<u8>) {
+    url_decode(src, dst);
+}
+
+/// Decode a URL-encoded value into the given Vector, replacing its contents.
+///
+/// The capacity of `dst` is reused.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::url_decode_replace;
+///
+/// let mut output = b"Goodbye".to_vec();
+///
+/// url_decode_replace(b"Hello%20world%21", &mut output);
+/// assert_eq!(b"Hello world!", &output[..]);
+/// ```
+#[inline]
+pub fn url_decode_replace(src: &[u8], dst: &mut Vec<u8>) {
+    dst.clear();
+    url_decode(src, dst);
 }
 
 #[cfg(test)]
 mod tests {
     #![allow(non_snake_case)]
 
-    use super::url_decode;
+    use super::{decoded_len, needs_decoding, url_decode, url_decode_append, url_decode_replace, Mode};
 
     #[test]
     fn smoke_test() {
@@ -58,4 +585,44 @@ mod tests {
         url_decode(v, &mut result);
         assert_eq!(b" \0\0\0\0\0\0\0\0\0\0\0\0\0 \0\0\0\0\0\0\0\0\0\0\0\0\0", &result[..])
     }
+
+    #[test]
+    fn test_append() {
+        // Long enough to use every implementation.
+        let v = b"%41%42\0\0\0\0\0\0\0\0\0\0\0\0\0\0+%43";
+        let mut result = b"start".to_vec();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"startAB\0\0\0\0\0\0\0\0\0\0\0\0\0\0 C", &result[..]);
+
+        url_decode_append(b"%20end", &mut result);
+        assert_eq!(b"startAB\0\0\0\0\0\0\0\0\0\0\0\0\0\0 C end", &result[..]);
+    }
+
+    #[test]
+    fn test_replace() {
+        let v = b"%41%42\0\0\0\0\0\0\0\0\0\0\0\0\0\0+%43";
+        let mut result = b"start".to_vec();
+
+        url_decode_replace(v, &mut result);
+        assert_eq!(b"AB\0\0\0\0\0\0\0\0\0\0\0\0\0\0 C", &result[..]);
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        let v = b"%41%42\0\0\0\0\0\0\0\0\0\0\0\0\0\0+%43%4";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(result.len(), decoded_len(v));
+    }
+
+    #[test]
+    fn test_needs_decoding() {
+        // Long enough to use every implementation.
+        let v = b"\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0+%4";
+        assert!(!needs_decoding(v, Mode::Path));
+        assert!(needs_decoding(v, Mode::Form));
+        assert!(needs_decoding(b"%41", Mode::Path));
+    }
 }
@@ -0,0 +1,76 @@
+//! Decoding in a given [`Mode`].
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+use crate::simd::{self, SimdVector};
+use crate::{swar, Mode};
+
+/// Decode `src` in `mode` and append the result to `dst`.
+pub(crate) fn url_decode_mode(src: &[u8], dst: &mut Vec<u8>, mode: Mode) {
+    match mode {
+        Mode::Path => url_decode_path(src, dst),
+        Mode::Form => crate::url_decode(src, dst),
+    }
+}
+
+#[inline]
+fn url_decode_path(src: &[u8], dst: &mut Vec<u8>) {
+    dispatch! {
+        avx2 => unsafe { crate::avx2::url_decode_with(src, dst, &mut KeepPlus) },
+        sse41 => unsafe { crate::sse41::url_decode_with(src, dst, &mut KeepPlus) },
+        portable => crate::portable::url_decode_with(src, dst, &mut KeepPlus),
+        swar => swar::url_decode_with(src, dst, &mut KeepPlus),
+    }
+}
+
+/// Decodes every escape and keeps `+` as it is.
+struct KeepPlus;
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+impl<V: SimdVector> simd::Hook<V> for KeepPlus {
+    const PLUS_AS_SPACE: bool = false;
+}
+
+impl swar::Hook for KeepPlus {
+    const PLUS_AS_SPACE: bool = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::url_decode_mode;
+    use crate::Mode;
+
+    fn decode(src: &[u8], mode: Mode) -> Vec<u8> {
+        let mut result = b"start".to_vec();
+        url_decode_mode(src, &mut result, mode);
+        result.split_off(5)
+    }
+
+    #[test]
+    fn test_modes() {
+        assert_eq!(b"a b+c", &decode(b"a+b%2Bc", Mode::Form)[..]);
+        assert_eq!(b"a+b+c", &decode(b"a+b%2Bc", Mode::Path)[..]);
+    }
+
+    #[test]
+    fn test_path_matches_fallback() {
+        let mut v = Vec::new();
+        for byte in 0..=255u8 {
+            v.extend_from_slice(format!("+%{:02X}a+%{:02x}%", byte, byte).as_bytes());
+        }
+
+        for start in 0..40 {
+            // Escaping `+` keeps it when decoding in form mode.
+            let mut escaped = Vec::new();
+            for &byte in &v[start..] {
+                match byte {
+                    b'+' => escaped.extend_from_slice(b"%2B"),
+                    _ => escaped.push(byte),
+                }
+            }
+            let mut expected = Vec::new();
+            crate::fallback::url_decode(&escaped, &mut expected);
+
+            assert_eq!(expected, decode(&v[start..], Mode::Path), "start {}", start);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Percent-encoding normalization, as described in RFC 3986 section 6.2.2.
+
+use crate::escapes::{encode_escape, EscapePositions};
+
+/// Check if `byte` is an unreserved character, which never needs to be escaped.
+#[inline]
+pub(crate) fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Append the normalized form of `src` to `dst`.
+///
+/// Runs of bytes between escapes are copied as they are.
+pub(crate) fn normalize_percent_encoding(src: &[u8], dst: &mut Vec<u8>) {
+    dst.reserve(src.len());
+    let mut pos = 0;
+
+    for escape in EscapePositions::new(src).filter(|escape| escape.valid) {
+        dst.extend_from_slice(&src[pos..escape.offset]);
+
+        if is_unreserved(escape.byte) {
+            dst.push(escape.byte);
+        } else {
+            dst.extend_from_slice(&encode_escape(escape.byte));
+        }
+        pos = escape.offset + 3;
+    }
+
+    dst.extend_from_slice(&src[pos..]);
+}
+
+/// Check if normalizing `src` would leave it unchanged.
+pub(crate) fn is_percent_encoding_normalized(src: &[u8]) -> bool {
+    EscapePositions::new(src)
+        .filter(|escape| escape.valid)
+        .all(|escape| {
+            !is_unreserved(escape.byte)
+                && !src[escape.offset + 1].is_ascii_lowercase()
+                && !src[escape.offset + 2].is_ascii_lowercase()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_percent_encoding_normalized, is_unreserved, normalize_percent_encoding};
+    use crate::test_util::{across_chunks, padded};
+
+    fn normalize(src: &[u8]) -> Vec<u8> {
+        let mut result = b"start".to_vec();
+        normalize_percent_encoding(src, &mut result);
+        result.split_off(5)
+    }
+
+    #[test]
+    fn test_is_unreserved() {
+        let unreserved: Vec<u8> = (0..=255).filter(|&byte| is_unreserved(byte)).collect();
+        assert_eq!(&b"-.0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz~"[..], &unreserved[..]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"", b""),
+            (b"/a/b?c=d+e", b"/a/b?c=d+e"),
+            (b"%7euser/%41%2d%5F", b"~user/A-_"),
+            (b"%2f%3A%e2%82%ac", b"%2F%3A%E2%82%AC"),
+            (b"100%%zz%4", b"100%%zz%4"),
+            (b"%25%32%65", b"%252e"),
+        ];
+
+        for &(src, expected) in cases {
+            assert_eq!(expected, &normalize(src)[..], "input {:?}", src);
+            assert!(is_percent_encoding_normalized(expected), "input {:?}", expected);
+            assert_eq!(src == expected, is_percent_encoding_normalized(src), "input {:?}", src);
+        }
+    }
+
+    #[test]
+    fn test_normalize_every_escape() {
+        for byte in 0..=255u8 {
+            for v in &[format!("ab%{:02x}cd", byte), format!("ab%{:02X}cd", byte)] {
+                let result = normalize(v.as_bytes());
+                let expected = if is_unreserved(byte) {
+                    vec![b'a', b'b', byte, b'c', b'd']
+                } else {
+                    format!("ab%{:02X}cd", byte).into_bytes()
+                };
+
+                assert_eq!(expected, result, "input {:?}", v);
+                assert!(is_percent_encoding_normalized(&result));
+            }
+        }
+    }
+
+    #[test]
+    fn test_across_chunks() {
+        across_chunks(b"%2f%41%3a%7E", |len, v| {
+            let expected = padded(len, b"%2FA%3A~");
+            assert_eq!(expected, normalize(v));
+            assert!(!is_percent_encoding_normalized(v));
+            assert!(is_percent_encoding_normalized(&expected));
+        });
+    }
+}
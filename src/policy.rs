@@ -0,0 +1,240 @@
+//! Policies for escaped NUL and control characters, enforced while decoding.
+
+use std::error::Error;
+use std::fmt;
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+use crate::simd::{self, SimdVector};
+use crate::swar;
+
+/// What to do with an escape which decodes to a control character.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlPolicy {
+    /// Decode it like any other escape.
+    Allow,
+    /// Fail with a [`ControlError`].
+    Reject,
+    /// Copy the escape to the output without decoding it.
+    KeepEncoded,
+    /// Decode it to the given byte instead.
+    Replace(u8),
+}
+
+/// Policies for each class of control character, used by
+/// [`url_decode_with_policy`](crate::url_decode_with_policy).
+///
+/// Only escapes are affected. Control characters which are not escaped are copied
+/// as they are. By default every class is allowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ControlPolicies {
+    /// `%00`.
+    pub nul: ControlPolicy,
+    /// `%0D` and `%0A`.
+    pub cr_lf: ControlPolicy,
+    /// Every other C0 control character, `%01` to `%1F`.
+    pub other_c0: ControlPolicy,
+}
+
+impl Default for ControlPolicies {
+    fn default() -> Self {
+        ControlPolicies {
+            nul: ControlPolicy::Allow,
+            cr_lf: ControlPolicy::Allow,
+            other_c0: ControlPolicy::Allow,
+        }
+    }
+}
+
+/// An escaped control character rejected by a [`ControlPolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ControlError {
+    offset: usize,
+    byte: u8,
+}
+
+impl ControlError {
+    /// Offset of the `%` of the escape in the input.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The control character the escape decodes to.
+    pub fn byte(&self) -> u8 {
+        self.byte
+    }
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "escaped control character {:#04x} at offset {}", self.byte, self.offset)
+    }
+}
+
+impl Error for ControlError {}
+
+/// Decode `src` and append the result to `dst`, applying `policies` to escaped
+/// control characters.
+///
+/// If an escape is rejected, `dst` is left as it was.
+pub(crate) fn url_decode_with_policy(src: &[u8], dst: &mut Vec<u8>, policies: &ControlPolicies) -> Result<(), ControlError> {
+    let dst_len = dst.len();
+    let mut enforcer = Enforcer { policies: *policies, error: None };
+
+    dispatch!(url_decode_with(src, dst, &mut enforcer));
+
+    match enforcer.error {
+        Some(error) => {
+            dst.truncate(dst_len);
+            Err(error)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Applies [`ControlPolicies`] to the escapes passed to it by a decoder.
+struct Enforcer {
+    policies: ControlPolicies,
+    /// The first rejected escape. Decoding continues but the output is discarded.
+    error: Option<ControlError>,
+}
+
+impl Enforcer {
+    #[inline]
+    fn reject(&mut self, offset: usize, byte: u8) {
+        // Classes are checked one at a time, so a later escape may be seen first.
+        match self.error {
+            Some(error) if error.offset < offset => {}
+            _ => self.error = Some(ControlError { offset, byte }),
+        }
+    }
+}
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+impl<V: SimdVector> simd::Hook<V> for Enforcer {
+    #[inline(always)]
+    unsafe fn escapes(&mut self, offset: usize, values: V, found: V) -> (V, V) {
+        if found.is_zero() {
+            return (values, found);
+        }
+
+        let nul = values.cmpeq(V::splat(0));
+        let cr_lf = values.cmpeq(V::splat(b'\r')).or(values.cmpeq(V::splat(b'\n')));
+        let c0 = simd::control_mask(values);
+        let other_c0 = c0.blend(V::splat(0), nul.or(cr_lf));
+
+        let mut values = values;
+        let mut found = found;
+        for &(class, policy) in &[(nul, self.policies.nul), (cr_lf, self.policies.cr_lf), (other_c0, self.policies.other_c0)] {
+            let class = class.and(found);
+
+            match policy {
+                ControlPolicy::Allow => {}
+                ControlPolicy::Reject => {
+                    let mask = class.movemask();
+                    if mask != 0 {
+                        let i = mask.trailing_zeros() as usize;
+                        let mut bytes = [0u8; simd::MAX_LANES];
+                        values.store(bytes.as_mut_ptr());
+                        self.reject(offset + i, bytes[i]);
+                    }
+                }
+                ControlPolicy::KeepEncoded => found = found.blend(V::splat(0), class),
+                ControlPolicy::Replace(byte) => values = values.blend(V::splat(byte), class),
+            }
+        }
+
+        (values, found)
+    }
+}
+
+impl swar::Hook for Enforcer {
+    #[inline(always)]
+    fn escape(&mut self, offset: usize, byte: u8) -> Option<u8> {
+        let policy = match byte {
+            0 => self.policies.nul,
+            b'\r' | b'\n' => self.policies.cr_lf,
+            0x01..=0x1f => self.policies.other_c0,
+            _ => return Some(byte),
+        };
+
+        match policy {
+            ControlPolicy::Allow => Some(byte),
+            ControlPolicy::Reject => {
+                self.reject(offset, byte);
+                Some(byte)
+            }
+            ControlPolicy::KeepEncoded => None,
+            ControlPolicy::Replace(replacement) => Some(replacement),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{url_decode_with_policy, ControlPolicies, ControlPolicy};
+    use crate::test_util::{across_chunks, padded};
+
+    fn decode(src: &[u8], policies: &ControlPolicies) -> Result<Vec<u8>, (usize, u8)> {
+        let mut result = b"start".to_vec();
+        match url_decode_with_policy(src, &mut result, policies) {
+            Ok(()) => Ok(result[5..].to_vec()),
+            Err(error) => {
+                assert_eq!(b"start", &result[..]);
+                Err((error.offset(), error.byte()))
+            }
+        }
+    }
+
+    #[test]
+    fn test_allow() {
+        let v = b"a%00b%0D%0Ac%01%1f%20%7F";
+        assert_eq!(Ok(b"a\x00b\r\nc\x01\x1f \x7f".to_vec()), decode(v, &ControlPolicies::default()));
+    }
+
+    #[test]
+    fn test_reject() {
+        let policies = ControlPolicies { cr_lf: ControlPolicy::Reject, ..ControlPolicies::default() };
+
+        assert_eq!(Ok(b"a\x00b".to_vec()), decode(b"a%00b", &policies));
+        assert_eq!(Err((4, b'\n')), decode(b"a%00%0a%0d", &policies));
+        assert_eq!(Ok(b"a\r\n".to_vec()), decode(b"a\r\n", &policies));
+    }
+
+    #[test]
+    fn test_keep_encoded() {
+        let policies = ControlPolicies {
+            nul: ControlPolicy::KeepEncoded,
+            cr_lf: ControlPolicy::KeepEncoded,
+            other_c0: ControlPolicy::Allow,
+        };
+
+        assert_eq!(Ok(b"a%00b%0d%0A\x01 c".to_vec()), decode(b"a%00b%0d%0A%01%20c", &policies));
+    }
+
+    #[test]
+    fn test_replace() {
+        let policies = ControlPolicies {
+            nul: ControlPolicy::Replace(b'?'),
+            cr_lf: ControlPolicy::Replace(b' '),
+            other_c0: ControlPolicy::Replace(b'_'),
+        };
+
+        assert_eq!(Ok(b"a?b  _\xc1".to_vec()), decode(b"a%00b%0d%0A%01%C1", &policies));
+    }
+
+    #[test]
+    fn test_across_chunks() {
+        // Escapes at the end of the first chunk of every implementation.
+        across_chunks(b"%00%0a%41%1F", |len, v| {
+            let policies = ControlPolicies {
+                nul: ControlPolicy::KeepEncoded,
+                cr_lf: ControlPolicy::Replace(b'.'),
+                other_c0: ControlPolicy::Allow,
+            };
+            assert_eq!(Ok(padded(len, b"%00.A\x1f")), decode(v, &policies));
+
+            let policies = ControlPolicies { other_c0: ControlPolicy::Reject, ..ControlPolicies::default() };
+            assert_eq!(Err((len + 9, 0x1f)), decode(v, &policies));
+        });
+    }
+}
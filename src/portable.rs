@@ -0,0 +1,326 @@
+use std::ptr;
+use std::simd::prelude::*;
+use std::simd::Simd;
+
+use crate::simd::{self, Hook, SimdVector};
+use crate::validation::{ValidationError, ValidationRules};
+use crate::Mode;
+use crate::swar;
+
+/// This is a `std::simd` implementation of URL decode. It requires a nightly compiler.
+///
+/// The result is appended to `dst`, keeping any existing contents.
+///
+/// It uses the same algorithm as the SSE4.1 implementation without any architecture
+/// specific intrinsics, so it can be used as a reference for new architectures. How
+/// fast it is depends on how well the compiler lowers `swizzle_dyn` for the target.
+///
+/// No validation of UTF-8 data is performed so if a string is desired,
+/// it should be sanitised with eg [String::from_utf8_lossy]
+/// (https://doc.rust-lang.org/std/string/struct.String.html#method.from_utf8_lossy)
+pub fn url_decode(src: &[u8], dst: &mut Vec<u8>) {
+    unsafe { simd::url_decode::<u8x16>(src, dst) }
+}
+
+/// Decode a URL-encoded value and append the result to `dst`, passing the escapes
+/// of each vector through `hook`.
+pub(crate) fn url_decode_with<H: Hook<u8x16>>(src: &[u8], dst: &mut Vec<u8>, hook: &mut H) {
+    unsafe { simd::url_decode_with(src, dst, hook) }
+}
+
+/// Decode a URL-encoded value to `dst`, which has room for `capacity` bytes.
+///
+/// `capacity` must be at least `src.len()`. Returns the number of bytes written.
+pub(crate) unsafe fn decode_to(src: &[u8], dst: *mut u8, capacity: usize) -> usize {
+    simd::decode_to::<u8x16>(src, dst, capacity)
+}
+
+/// Count the bytes that [`url_decode`] would output for `src` without decoding it.
+pub fn decoded_len(src: &[u8]) -> usize {
+    unsafe { simd::decoded_len::<u8x16>(src) }
+}
+
+/// Check if decoding `src` in the given mode would change it.
+pub fn needs_decoding(src: &[u8], mode: Mode) -> bool {
+    unsafe { simd::needs_decoding::<u8x16>(src, mode) }
+}
+
+/// Check that every `%` in `src` starts a valid escape, and optionally that every
+/// byte is allowed by RFC 3986.
+pub fn validate(src: &[u8], rules: &ValidationRules) -> Result<(), ValidationError> {
+    unsafe { simd::validate::<u8x16>(src, rules) }
+}
+
+/// Find the percent symbols and valid escapes in the first 16 bytes of `src`.
+pub(crate) fn escape_masks(src: &[u8]) -> (usize, u32, u32) {
+    unsafe { simd::escape_masks::<u8x16>(src) }
+}
+
+/// Indices of each byte in a vector.
+const IOTA: u8x16 = u8x16::from_array([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+
+impl SimdVector for u8x16 {
+    const LANES: usize = 16;
+
+    #[inline(always)]
+    unsafe fn splat(byte: u8) -> Self {
+        u8x16::splat(byte)
+    }
+
+    #[inline(always)]
+    unsafe fn load(ptr: *const u8) -> Self {
+        u8x16::from_array(ptr::read_unaligned(ptr as *const [u8; 16]))
+    }
+
+    #[inline(always)]
+    unsafe fn load_partial(src: &[u8]) -> Self {
+        let (lo, hi) = if src.len() > 8 {
+            let lo = u64::from_le_bytes(ptr::read_unaligned(src.as_ptr() as *const [u8; 8]));
+            (lo, swar::load_partial(src.get_unchecked(8..)))
+        } else {
+            (swar::load_partial(src), 0)
+        };
+
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&lo.to_le_bytes());
+        bytes[8..].copy_from_slice(&hi.to_le_bytes());
+        u8x16::from_array(bytes)
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut u8) {
+        ptr::write_unaligned(ptr as *mut [u8; 16], self.to_array())
+    }
+
+    #[inline(always)]
+    unsafe fn cmpeq(self, other: Self) -> Self {
+        to_vector(self.simd_eq(other))
+    }
+
+    #[inline(always)]
+    unsafe fn cmpgt(self, other: Self) -> Self {
+        to_vector(self.cast::<i8>().simd_gt(other.cast::<i8>()))
+    }
+
+    #[inline(always)]
+    unsafe fn cmplt(self, other: Self) -> Self {
+        to_vector(self.cast::<i8>().simd_lt(other.cast::<i8>()))
+    }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self {
+        self & other
+    }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self {
+        self | other
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        self + other
+    }
+
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        self - other
+    }
+
+    #[inline(always)]
+    unsafe fn blend(self, other: Self, mask: Self) -> Self {
+        (self & !mask) | (other & mask)
+    }
+
+    #[inline(always)]
+    unsafe fn shl16<const BITS: i32>(self) -> Self {
+        let lanes: Simd<u16, 8> = std::mem::transmute(self);
+        std::mem::transmute(lanes << BITS as u16)
+    }
+
+    #[inline(always)]
+    unsafe fn shift_down<const BYTES: i32>(self) -> Self {
+        // Indices past the end select zero.
+        self.swizzle_dyn(IOTA + u8x16::splat(BYTES as u8))
+    }
+
+    #[inline(always)]
+    unsafe fn shuffle(self, indices: Self) -> Self {
+        self.swizzle_dyn(indices)
+    }
+
+    #[inline(always)]
+    unsafe fn movemask(self) -> u32 {
+        self.cast::<i8>().simd_lt(i8x16::splat(0)).to_bitmask() as u32
+    }
+
+    #[inline(always)]
+    unsafe fn is_zero(self) -> bool {
+        self.reduce_or() == 0
+    }
+
+    /// Compacts using the same lookup table of shuffles as the SSE4.1 implementation.
+    #[inline(always)]
+    unsafe fn compact_store(self, found_mask: u32, carry: usize, dst: *mut u8) -> (usize, usize) {
+        simd::compact_store_16(self, found_mask, carry, dst)
+    }
+
+    #[inline(always)]
+    unsafe fn print(self, _msg: &str) {
+        print_m128i!(_msg, self);
+    }
+}
+
+/// Convert a mask to a vector with every bit of the selected bytes set.
+#[inline(always)]
+fn to_vector(mask: Mask<i8, 16>) -> u8x16 {
+    mask.select(u8x16::splat(0xff), u8x16::splat(0))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    use super::{url_decode, decoded_len, needs_decoding, validate};
+    use crate::test_util::{assert_decoded_len, assert_matches_fallback, assert_needs_decoding, assert_validate, Backend};
+
+    fn backend() -> Backend {
+        Backend { url_decode, decoded_len, needs_decoding, validate }
+    }
+
+    #[test]
+    fn url_decode_space() {
+        let v = b"%20\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b" \0\0\0\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_A() {
+        let v = b"%41\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"A\0\0\0\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AB() {
+        let v = b"%41%42\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"AB\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AaBb_numbers() {
+        let v = b"%41a%42b12345678";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"AaBb12345678", &result[..])
+    }
+
+    #[test]
+    fn url_decode_upper_hex_KaLb_numbers() {
+        let v = b"%4Ba%4Cb12345678";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"KaLb12345678", &result[..])
+    }
+
+    #[test]
+    fn url_decode_lower_hex_KaLb_numbers() {
+        let v = b"%4ba%4cb12345678";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"KaLb12345678", &result[..])
+    }
+
+    #[test]
+    fn test_decode_invalid_chars() {
+        let mut result = Vec::new();
+
+        let v = b"%%12345678901234";
+        url_decode(v, &mut result);
+        assert_eq!(b"%\x12345678901234", &result[..]);
+
+        let v = b"%1%2345678901234";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"%1\x2345678901234", &result[..]);
+
+        let v = b"%-12345678901234";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"%-12345678901234", &result[..]);
+    }
+
+    #[test]
+    fn test_split_percent() {
+        let mut result = Vec::new();
+
+        // last char of block is %
+        let v = b"aaaaaaaaaaaaaaa%aaaaaaaaaaaaaaaa";
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaaaaaaaaaaa\xAAaaaaaaaaaaaaaa", &result[..]);
+
+        // escape at the last byte of the first chunk
+        let v = b"aaaaaaaaaaaaaaa%41aaaaaaaaaaaaaaaaaa";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaaaaaaaaaaaAaaaaaaaaaaaaaaaaaa", &result[..]);
+    }
+
+    #[test]
+    fn test_replace_plus() {
+        let mut result = Vec::new();
+
+        let v = b"a+a+a+a+a+a+a+a+";
+        url_decode(v, &mut result);
+        assert_eq!(b"a a a a a a a a ", &result[..]);
+    }
+
+    #[test]
+    fn test_random_junk() {
+        let mut result = Vec::new();
+
+        let v = b"\xCF%%sA\x00`A%5%%6%6\xEF";
+        url_decode(v, &mut result);
+        assert_eq!(b"\xCF%%sA\x00`A%5%%6%6\xEF", &result[..]);
+    }
+
+    #[test]
+    fn test_append() {
+        let mut result = b"abc".to_vec();
+
+        let v = b"%41a%42b12345678%43";
+        url_decode(v, &mut result);
+        assert_eq!(b"abcAaBb12345678C", &result[..]);
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        assert_matches_fallback(backend(), 0..100, 100);
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        assert_decoded_len(backend());
+    }
+
+    #[test]
+    fn test_needs_decoding() {
+        assert_needs_decoding(backend());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_validate(backend());
+    }
+}
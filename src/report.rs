@@ -0,0 +1,219 @@
+//! Flags for suspicious escapes, collected while decoding.
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+use crate::simd::{self, SimdVector};
+use crate::{fallback, swar};
+
+/// Suspicious escapes found by [`url_decode_with_report`](crate::url_decode_with_report).
+///
+/// Only escapes are inspected. The same bytes appearing unescaped are not flagged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeReport {
+    /// An escaped NUL, `%00`.
+    pub encoded_nul: bool,
+    /// An escaped control character other than NUL, `%01` to `%1F` or `%7F`.
+    pub encoded_control: bool,
+    /// An escaped path separator, `%2F` or `%5C`.
+    pub encoded_separator: bool,
+    /// An escape which can only start an overlong UTF-8 encoding. This is `%C0` or
+    /// `%C1`, eg `%C0%AF` for `/`, `%E0` followed by `%80` to `%9F`, or `%F0` followed
+    /// by `%80` to `%8F`.
+    pub overlong_utf8: bool,
+    /// A `.` or `..` path segment with an escaped dot, eg `%2e%2e` or `.%2E`.
+    pub encoded_dot_segment: bool,
+}
+
+impl DecodeReport {
+    /// Check if nothing suspicious was found.
+    pub fn is_clean(&self) -> bool {
+        *self == DecodeReport::default()
+    }
+}
+
+/// Decode `src` and append the result to `dst`, reporting suspicious escapes.
+pub(crate) fn url_decode_with_report(src: &[u8], dst: &mut Vec<u8>) -> DecodeReport {
+    let mut inspector = Inspector { src, report: DecodeReport::default() };
+    dispatch!(url_decode_with(src, dst, &mut inspector));
+    inspector.report
+}
+
+/// Collects a [`DecodeReport`] from the escapes passed to it by a decoder.
+struct Inspector<'a> {
+    src: &'a [u8],
+    report: DecodeReport,
+}
+
+impl<'a> Inspector<'a> {
+    /// Check if the escaped dot at `offset` is part of a dot segment.
+    #[inline(never)]
+    fn dot(&mut self, offset: usize) {
+        // The segment can start at the escape or at a dot before it.
+        let dot_segment = [0, 1, 3].iter()
+            .filter_map(|&back| offset.checked_sub(back))
+            .filter(|&start| start == 0 || self.src[start - 1] == b'/')
+            .any(|start| matches!(dot_segment_end(self.src, start), Some(end) if end > offset));
+
+        self.report.encoded_dot_segment |= dot_segment;
+    }
+
+    /// Check if the escaped `E0` or `F0` at `offset` starts an overlong encoding,
+    /// which depends on the escape after it.
+    #[inline(never)]
+    fn overlong_lead(&mut self, offset: usize) {
+        let escape = |start: usize| match self.src.get(start..start + 3) {
+            Some(&[b'%', high, low]) => fallback::decode_hex(high, low),
+            _ => None,
+        };
+
+        self.report.overlong_utf8 |= match (escape(offset), escape(offset + 3)) {
+            (Some(0xe0), Some(next)) => (0x80..=0x9f).contains(&next),
+            (Some(0xf0), Some(next)) => (0x80..=0x8f).contains(&next),
+            _ => false,
+        };
+    }
+}
+
+/// Find the end of a `.` or `..` segment at `start`, where each dot may be escaped.
+fn dot_segment_end(src: &[u8], start: usize) -> Option<usize> {
+    let mut pos = start;
+
+    for _ in 0..2 {
+        match src.get(pos..) {
+            Some([b'.', ..]) => pos += 1,
+            Some([b'%', b'2', b'e', ..]) | Some([b'%', b'2', b'E', ..]) => pos += 3,
+            _ => break,
+        }
+    }
+
+    match src.get(pos) {
+        _ if pos == start => None,
+        None | Some(b'/') | Some(b'?') | Some(b'#') => Some(pos),
+        _ => None,
+    }
+}
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+impl<'a, V: SimdVector> simd::Hook<V> for Inspector<'a> {
+    #[inline(always)]
+    unsafe fn escapes(&mut self, offset: usize, values: V, found: V) -> (V, V) {
+        let found_mask = found.movemask();
+        if found_mask == 0 {
+            return (values, found);
+        }
+
+        let nul = values.cmpeq(V::splat(0));
+        let control = simd::control_mask(values).or(values.cmpeq(V::splat(0x7f)));
+        let separator = values.cmpeq(V::splat(b'/')).or(values.cmpeq(V::splat(b'\\')));
+        let overlong = values.cmpeq(V::splat(0xc0)).or(values.cmpeq(V::splat(0xc1)));
+        let lead = values.cmpeq(V::splat(0xe0)).or(values.cmpeq(V::splat(0xf0)));
+        let dot = values.cmpeq(V::splat(b'.'));
+
+        let nul = nul.movemask() & found_mask;
+        self.report.encoded_nul |= nul != 0;
+        self.report.encoded_control |= control.movemask() & found_mask & !nul != 0;
+        self.report.encoded_separator |= separator.movemask() & found_mask != 0;
+        self.report.overlong_utf8 |= overlong.movemask() & found_mask != 0;
+
+        let mut dots = dot.movemask() & found_mask;
+        while dots != 0 {
+            self.dot(offset + dots.trailing_zeros() as usize);
+            dots &= dots - 1;
+        }
+
+        let mut leads = lead.movemask() & found_mask;
+        while leads != 0 {
+            self.overlong_lead(offset + leads.trailing_zeros() as usize);
+            leads &= leads - 1;
+        }
+
+        (values, found)
+    }
+}
+
+impl<'a> swar::Hook for Inspector<'a> {
+    #[inline(always)]
+    fn escape(&mut self, offset: usize, byte: u8) -> Option<u8> {
+        match byte {
+            0 => self.report.encoded_nul = true,
+            0x01..=0x1f | 0x7f => self.report.encoded_control = true,
+            b'/' | b'\\' => self.report.encoded_separator = true,
+            0xc0 | 0xc1 => self.report.overlong_utf8 = true,
+            0xe0 | 0xf0 => self.overlong_lead(offset),
+            b'.' => self.dot(offset),
+            _ => {}
+        }
+
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{url_decode_with_report, DecodeReport};
+    use crate::test_util::across_chunks;
+
+    fn report(src: &[u8]) -> DecodeReport {
+        let mut result = Vec::new();
+        let report = url_decode_with_report(src, &mut result);
+
+        let mut expected = Vec::new();
+        crate::fallback::url_decode(src, &mut expected);
+        assert_eq!(expected, result);
+
+        report
+    }
+
+    #[test]
+    fn test_clean() {
+        assert!(report(b"").is_clean());
+        assert!(report(b"/a/b%20c/./../d?e=%41\x00").is_clean());
+        assert!(report(b"%2e%2e%2e/a%2eb/.%2e.").is_clean());
+    }
+
+    #[test]
+    fn test_flags() {
+        assert_eq!(DecodeReport { encoded_nul: true, ..DecodeReport::default() }, report(b"a%00"));
+        assert_eq!(DecodeReport { encoded_control: true, ..DecodeReport::default() }, report(b"a%0d%0A"));
+        assert_eq!(DecodeReport { encoded_control: true, ..DecodeReport::default() }, report(b"%7f"));
+        assert_eq!(DecodeReport { encoded_separator: true, ..DecodeReport::default() }, report(b"..%2f..%5C"));
+        assert_eq!(DecodeReport { overlong_utf8: true, ..DecodeReport::default() }, report(b"%C0%AF"));
+    }
+
+    #[test]
+    fn test_overlong() {
+        let overlong = DecodeReport { overlong_utf8: true, ..DecodeReport::default() };
+
+        for v in &[&b"%C1%81"[..], b"%E0%80%AF", b"%e0%9f%bf", b"%F0%80%80%AF", b"%f0%8F%bf%bf"] {
+            assert_eq!(overlong, report(v), "input {:?}", v);
+        }
+
+        // Valid encodings, and leads which aren't followed by an escape.
+        for v in &[&b"%E0%A0%80"[..], b"%F0%90%80%80", b"%E1%80%80", b"%E0\x80", b"%E0%8", b"%F0"] {
+            assert!(report(v).is_clean(), "input {:?}", v);
+        }
+    }
+
+    #[test]
+    fn test_dot_segments() {
+        let dot_segment = DecodeReport { encoded_dot_segment: true, ..DecodeReport::default() };
+
+        for v in &[&b"%2e"[..], b"%2E%2e", b"/a/.%2e/b", b"/a/%2e./b", b"/a/%2e?x", b"%2e#", b"a/%2e%2e"] {
+            assert_eq!(dot_segment, report(v), "input {:?}", v);
+        }
+    }
+
+    #[test]
+    fn test_across_chunks() {
+        // Flags at the end of the first chunk of every implementation.
+        across_chunks(b"/%2e%2e/%00", |_, v| {
+            let report = report(v);
+            assert!(report.encoded_nul && report.encoded_dot_segment);
+            assert!(!report.encoded_control && !report.encoded_separator && !report.overlong_utf8);
+        });
+
+        // The escape after the lead is in the next vector.
+        for middle in &[&b"%E0%9F%BF"[..], b"%F0%8F%BF%BF"] {
+            across_chunks(middle, |_, v| assert_eq!(DecodeReport { overlong_utf8: true, ..DecodeReport::default() }, report(v)));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Mapping request paths to filesystem paths under a root directory.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::segments::PathSegments;
+
+/// The reason [`decode_to_safe_path`](crate::decode_to_safe_path) rejected a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathError {
+    /// A segment contains a NUL byte.
+    Nul,
+    /// A segment contains a backslash, which is a separator on some platforms.
+    Backslash,
+    /// A segment contains an escaped `/`.
+    EncodedSlash,
+    /// A `..` segment would leave the root.
+    EscapesRoot,
+    /// A segment is not a plain file name on this platform, eg a drive prefix.
+    InvalidSegment,
+    /// A segment is not valid UTF-8, on platforms where paths must be Unicode.
+    InvalidUtf8,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            PathError::Nul => "path contains a NUL byte",
+            PathError::Backslash => "path contains a backslash",
+            PathError::EncodedSlash => "path contains an escaped slash",
+            PathError::EscapesRoot => "path escapes the root directory",
+            PathError::InvalidSegment => "path segment is not a file name",
+            PathError::InvalidUtf8 => "path is not valid UTF-8",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for PathError {}
+
+/// Decode `path` and join it to `root`, removing dot segments.
+pub(crate) fn decode_to_safe_path(path: &[u8], root: &Path) -> Result<PathBuf, PathError> {
+    let mut segments: Vec<Cow<'_, [u8]>> = Vec::new();
+
+    // Remove dot segments as in RFC 3986 section 5.2.4, except that `..` at the
+    // root is an error rather than ignored.
+    for segment in PathSegments::new(path) {
+        match &segment[..] {
+            b"" | b"." => {}
+            b".." => {
+                segments.pop().ok_or(PathError::EscapesRoot)?;
+            }
+            bytes => {
+                check_segment(bytes)?;
+                segments.push(segment);
+            }
+        }
+    }
+
+    let mut result = root.to_path_buf();
+    for segment in &segments {
+        let segment = os_str(segment)?;
+
+        // Anything other than a single file name could replace or leave the root.
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if name == segment => result.push(segment),
+            _ => return Err(PathError::InvalidSegment),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Check a decoded segment for bytes which are unsafe in a file name.
+fn check_segment(segment: &[u8]) -> Result<(), PathError> {
+    for &byte in segment {
+        match byte {
+            0 => return Err(PathError::Nul),
+            b'\\' => return Err(PathError::Backslash),
+            b'/' => return Err(PathError::EncodedSlash),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn os_str(segment: &[u8]) -> Result<&OsStr, PathError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(OsStr::from_bytes(segment))
+}
+
+#[cfg(not(unix))]
+fn os_str(segment: &[u8]) -> Result<&OsStr, PathError> {
+    std::str::from_utf8(segment)
+        .map(OsStr::new)
+        .map_err(|_| PathError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{decode_to_safe_path, PathError};
+
+    fn safe_path(path: &[u8]) -> Result<PathBuf, PathError> {
+        decode_to_safe_path(path, Path::new("/srv/www"))
+    }
+
+    #[test]
+    fn test_safe_path() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"", "/srv/www"),
+            (b"/", "/srv/www"),
+            (b"/index.html", "/srv/www/index.html"),
+            (b"/a%20b/c+d.txt", "/srv/www/a b/c+d.txt"),
+            (b"/a/./b//c/", "/srv/www/a/b/c"),
+            (b"/a/b/../c", "/srv/www/a/c"),
+            (b"/a/%2e%2E/b/%2e", "/srv/www/b"),
+            (b"/a/.../b", "/srv/www/a/.../b"),
+            (b"a/..", "/srv/www"),
+        ];
+
+        for &(path, expected) in cases {
+            assert_eq!(Ok(PathBuf::from(expected)), safe_path(path), "path {:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_rejected() {
+        let cases: &[(&[u8], PathError)] = &[
+            (b"/a%00.txt", PathError::Nul),
+            (b"/a\x00", PathError::Nul),
+            (b"/a%5c..%5c..", PathError::Backslash),
+            (b"/a\\b", PathError::Backslash),
+            (b"/a/..%2f..%2Fetc", PathError::EncodedSlash),
+            (b"/..", PathError::EscapesRoot),
+            (b"/a/../../etc/passwd", PathError::EscapesRoot),
+            (b"/%2e%2e/etc", PathError::EscapesRoot),
+        ];
+
+        for &(path, expected) in cases {
+            assert_eq!(Err(expected), safe_path(path), "path {:?}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let expected = Path::new("/srv/www").join(OsStr::from_bytes(b"caf\xe9"));
+        assert_eq!(Ok(expected), safe_path(b"/caf%E9"));
+    }
+}
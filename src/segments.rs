@@ -0,0 +1,121 @@
+//! Splitting a path into segments and decoding each one.
+
+use std::borrow::Cow;
+
+use memchr::memchr2;
+
+use crate::mode::url_decode_mode;
+use crate::{needs_decoding, Mode};
+
+/// Iterator returned by [`decode_path_segments`](crate::decode_path_segments).
+///
+/// Segments without escapes are borrowed from the path.
+#[derive(Clone, Debug)]
+pub struct PathSegments<'a> {
+    /// The rest of the path after the last segment returned, or `None` at the end.
+    rest: Option<&'a [u8]>,
+}
+
+impl<'a> PathSegments<'a> {
+    pub(crate) fn new(path: &'a [u8]) -> Self {
+        let rest = match path {
+            [] => None,
+            [b'/', rest @ ..] => Some(rest),
+            _ => Some(path),
+        };
+
+        PathSegments { rest }
+    }
+}
+
+impl<'a> Iterator for PathSegments<'a> {
+    type Item = Cow<'a, [u8]>;
+
+    fn next(&mut self) -> Option<Cow<'a, [u8]>> {
+        let rest = self.rest?;
+        let mut has_percent = false;
+        let mut pos = 0;
+
+        let end = loop {
+            match memchr2(b'/', b'%', &rest[pos..]) {
+                Some(i) if rest[pos + i] == b'%' => {
+                    has_percent = true;
+                    pos += i + 1;
+                }
+                Some(i) => {
+                    self.rest = Some(&rest[pos + i + 1..]);
+                    break pos + i;
+                }
+                None => {
+                    self.rest = None;
+                    break rest.len();
+                }
+            }
+        };
+
+        // Hex digits are never `/`, so an escape can't span two segments.
+        let segment = &rest[..end];
+        Some(if has_percent && needs_decoding(segment, Mode::Path) {
+            let mut decoded = Vec::with_capacity(segment.len());
+            url_decode_mode(segment, &mut decoded, Mode::Path);
+            Cow::Owned(decoded)
+        } else {
+            Cow::Borrowed(segment)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::PathSegments;
+    use crate::test_util::random_inputs;
+
+    fn segments(path: &[u8]) -> Vec<Vec<u8>> {
+        PathSegments::new(path).map(Cow::into_owned).collect()
+    }
+
+    #[test]
+    fn test_segments() {
+        let cases: &[(&[u8], &[&[u8]])] = &[
+            (b"", &[]),
+            (b"/", &[b""]),
+            (b"/a%2Fb/c", &[b"a/b", b"c"]),
+            (b"a/b/", &[b"a", b"b", b""]),
+            (b"//a", &[b"", b"a"]),
+            (b"/a+b/%2B%20", &[b"a+b", b"+ "]),
+            (b"/%/%4/%zz%41/%", &[b"%", b"%4", b"%zzA", b"%"]),
+        ];
+
+        for &(path, expected) in cases {
+            assert_eq!(expected, &segments(path)[..], "path {:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_borrowed() {
+        let results: Vec<bool> = PathSegments::new(b"/a/%41/%zz/b%2f")
+            .map(|segment| matches!(segment, Cow::Borrowed(_)))
+            .collect();
+        assert_eq!(vec![true, false, true, false], results);
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        for v in random_inputs(b"%/aF0g9", 0..40, 50) {
+            let path = v.strip_prefix(b"/").unwrap_or(&v);
+            let expected: Vec<Vec<u8>> = if v.is_empty() {
+                Vec::new()
+            } else {
+                path.split(|&byte| byte == b'/').map(|segment| {
+                    let mut decoded = Vec::new();
+                    crate::fallback::url_decode(segment, &mut decoded);
+                    decoded
+                }).collect()
+            };
+
+            assert_eq!(expected, segments(&v), "path {:?}", v);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Decoding which keeps some escapes encoded, eg to preserve the structure of a path.
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+use crate::simd::{self, SimdVector};
+use crate::swar;
+
+/// A set of bytes whose escapes [`url_decode_selective`](crate::url_decode_selective)
+/// keeps encoded.
+///
+/// # Examples
+///
+/// ```
+/// use url_decode_simd::KeepEncodedSet;
+///
+/// let set = KeepEncodedSet::new(b"/?%");
+///
+/// assert!(set.contains(b'/'));
+/// assert!(!set.contains(b'A'));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeepEncodedSet {
+    bits: [u64; 4],
+}
+
+impl KeepEncodedSet {
+    /// Create a set of the given bytes.
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut set = KeepEncodedSet::default();
+        for &byte in bytes {
+            set.insert(byte);
+        }
+        set
+    }
+
+    /// Add `byte` to the set.
+    pub fn insert(&mut self, byte: u8) {
+        self.bits[byte as usize / 64] |= 1 << (byte % 64);
+    }
+
+    /// Remove `byte` from the set.
+    pub fn remove(&mut self, byte: u8) {
+        self.bits[byte as usize / 64] &= !(1 << (byte % 64));
+    }
+
+    /// Check if `byte` is in the set.
+    #[inline]
+    pub fn contains(&self, byte: u8) -> bool {
+        self.bits[byte as usize / 64] & (1 << (byte % 64)) != 0
+    }
+
+    /// Check if the set has no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.bits == [0; 4]
+    }
+}
+
+/// Decode `src` and append the result to `dst`, keeping the escapes of the bytes in
+/// `keep` encoded.
+pub(crate) fn url_decode_selective(src: &[u8], dst: &mut Vec<u8>, keep: &KeepEncodedSet) {
+    let mut keep = *keep;
+    dispatch!(url_decode_with(src, dst, &mut keep))
+}
+
+#[cfg(any(all(target_feature = "sse4.1", target_feature = "popcnt"), feature = "portable-simd"))]
+impl<V: SimdVector> simd::Hook<V> for KeepEncodedSet {
+    #[inline(always)]
+    unsafe fn escapes(&mut self, _offset: usize, values: V, found: V) -> (V, V) {
+        let found_mask = found.movemask();
+        if found_mask == 0 {
+            return (values, found);
+        }
+
+        // Escapes are sparse, so look up each one rather than comparing the vector
+        // against every byte in the set.
+        let mut bytes = [0u8; simd::MAX_LANES];
+        values.store(bytes.as_mut_ptr());
+
+        let mut keep_mask = 0u32;
+        let mut mask = found_mask;
+        while mask != 0 {
+            let i = mask.trailing_zeros();
+            if self.contains(bytes[i as usize]) {
+                keep_mask |= 1 << i;
+            }
+            mask &= mask - 1;
+        }
+
+        if keep_mask == 0 {
+            return (values, found);
+        }
+
+        // Clear the kept escapes from the mask vector.
+        let mut lanes = [0u8; simd::MAX_LANES];
+        found.store(lanes.as_mut_ptr());
+        while keep_mask != 0 {
+            lanes[keep_mask.trailing_zeros() as usize] = 0;
+            keep_mask &= keep_mask - 1;
+        }
+
+        (values, V::load(lanes.as_ptr()))
+    }
+}
+
+impl swar::Hook for KeepEncodedSet {
+    #[inline(always)]
+    fn escape(&mut self, _offset: usize, byte: u8) -> Option<u8> {
+        if self.contains(byte) {
+            None
+        } else {
+            Some(byte)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{url_decode_selective, KeepEncodedSet};
+    use crate::test_util::{across_chunks, padded};
+
+    fn decode(src: &[u8], keep: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        url_decode_selective(src, &mut result, &KeepEncodedSet::new(keep));
+        result
+    }
+
+    #[test]
+    fn test_set() {
+        let mut set = KeepEncodedSet::new(b"\x00/\x7f\x80\xff");
+        for byte in 0..=255u8 {
+            assert_eq!(b"\x00/\x7f\x80\xff".contains(&byte), set.contains(byte));
+        }
+
+        set.remove(b'/');
+        assert!(!set.contains(b'/'));
+        assert!(!set.is_empty());
+        assert!(KeepEncodedSet::default().is_empty());
+    }
+
+    #[test]
+    fn test_selective() {
+        assert_eq!(b"/a%2Fb%3f/%25A/A", &decode(b"/a%2Fb%3f/%25%41/%41", b"/?%")[..]);
+        assert_eq!(b"a b", &decode(b"a+b", b"/?%")[..]);
+        assert_eq!(b"%ff\x80%", &decode(b"%ff%80%", b"\xff")[..]);
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        // Without a set it decodes like `url_decode`, and with every byte in the set
+        // only `+` changes.
+        let mut v = Vec::new();
+        for byte in 0..=255u8 {
+            v.extend_from_slice(format!("%{:02X}+x%{:02x}", byte, byte).as_bytes());
+        }
+
+        let mut expected = Vec::new();
+        crate::fallback::url_decode(&v, &mut expected);
+        assert_eq!(expected, decode(&v, b""));
+
+        let all: Vec<u8> = (0..=255).collect();
+        let expected: Vec<u8> = v.iter().map(|&byte| if byte == b'+' { b' ' } else { byte }).collect();
+        assert_eq!(expected, decode(&v, &all));
+    }
+
+    #[test]
+    fn test_across_chunks() {
+        across_chunks(b"%2F%41%2f%3F", |len, v| assert_eq!(padded(len, b"%2FA%2f?"), decode(v, b"/")));
+    }
+}
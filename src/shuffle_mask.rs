@@ -1,4 +1,4 @@
-pub (crate) const SHUFFLE_MASK: [[u8; 16]; 16384] = [
+pub (crate) static SHUFFLE_MASK: [[u8; 16]; 16384] = [
     [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
     [0, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
     [0, 0, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
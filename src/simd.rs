@@ -0,0 +1,476 @@
+//! A URL decode kernel which is generic over the width of the SIMD vector.
+//!
+//! Each implementation provides the basic operations in [`SimdVector`] and a strategy
+//! for removing the hex digits of escapes from a vector once they are decoded.
+
+use std::cmp;
+
+use crate::shuffle_mask::SHUFFLE_MASK;
+use crate::validation::{self, ValidationError, ValidationErrorKind, ValidationRules};
+use crate::Mode;
+
+/// Number of bytes in the widest vector, for buffers which hold one vector. Masks
+/// of one bit per byte are `u32`, so no vector is wider.
+pub(crate) const MAX_LANES: usize = 32;
+
+/// Operations on a vector of bytes needed by the decode kernel.
+///
+/// Comparisons return a mask vector with every bit of matching bytes set.
+pub(crate) trait SimdVector: Copy {
+    /// Number of bytes in the vector.
+    const LANES: usize;
+
+    /// Set every byte to `byte`.
+    unsafe fn splat(byte: u8) -> Self;
+
+    /// Load `LANES` bytes from an unaligned address.
+    unsafe fn load(ptr: *const u8) -> Self;
+
+    /// Load up to `LANES` bytes, padded with zeros.
+    unsafe fn load_partial(src: &[u8]) -> Self;
+
+    /// Store `LANES` bytes to an unaligned address.
+    unsafe fn store(self, ptr: *mut u8);
+
+    unsafe fn cmpeq(self, other: Self) -> Self;
+
+    /// Signed comparison.
+    unsafe fn cmpgt(self, other: Self) -> Self;
+
+    /// Signed comparison.
+    unsafe fn cmplt(self, other: Self) -> Self;
+
+    unsafe fn and(self, other: Self) -> Self;
+
+    unsafe fn or(self, other: Self) -> Self;
+
+    unsafe fn add(self, other: Self) -> Self;
+
+    unsafe fn sub(self, other: Self) -> Self;
+
+    /// Select bytes from `other` where `mask` is set, else from `self`.
+    unsafe fn blend(self, other: Self, mask: Self) -> Self;
+
+    /// Shift each 16-bit lane left by `BITS`.
+    unsafe fn shl16<const BITS: i32>(self) -> Self;
+
+    /// Move every byte `BYTES` positions towards the start, shifting in zeros.
+    unsafe fn shift_down<const BYTES: i32>(self) -> Self;
+
+    /// Select the bytes at the indices in `indices`, within each 16 byte lane. Bytes
+    /// selected by indices past the end of the lane are unspecified.
+    unsafe fn shuffle(self, indices: Self) -> Self;
+
+    /// Reduce a mask vector to one bit per byte.
+    unsafe fn movemask(self) -> u32;
+
+    /// Check if all bytes are 0.
+    unsafe fn is_zero(self) -> bool;
+
+    /// Remove the hex digits of the escapes in `found_mask` from a vector returned
+    /// by [`decode_ahead`], as well as the first `carry` bytes, and store the
+    /// result at `dst`. Up to `LANES` bytes are written.
+    ///
+    /// Returns the number of bytes of output and the number of bytes at the start
+    /// of the next vector which are hex digits of an escape at the end of this one.
+    unsafe fn compact_store(self, found_mask: u32, carry: usize, dst: *mut u8) -> (usize, usize);
+
+    /// Print the vector when debugging.
+    unsafe fn print(self, msg: &str);
+}
+
+/// Inspects or changes how the escapes of each vector are decoded.
+pub(crate) trait Hook<V: SimdVector> {
+    /// Whether `+` is decoded to a space.
+    const PLUS_AS_SPACE: bool = true;
+
+    /// Called before the escapes of the vector at `offset` in the input are decoded.
+    ///
+    /// `values` holds the decoded byte of each escape at the position of its `%` and
+    /// `found` is a mask vector of the valid escapes. Returns the values and mask of
+    /// the escapes to decode. Escapes removed from the mask are kept as they are.
+    #[inline(always)]
+    unsafe fn escapes(&mut self, _offset: usize, values: V, found: V) -> (V, V) {
+        (values, found)
+    }
+}
+
+/// Decode as [`url_decode`](crate::url_decode).
+impl<V: SimdVector> Hook<V> for () {}
+
+/// Decode a URL-encoded value and append the result to the given Vector.
+#[inline(always)]
+pub(crate) unsafe fn url_decode<V: SimdVector>(src: &[u8], dst: &mut Vec<u8>) {
+    url_decode_with::<V, ()>(src, dst, &mut ())
+}
+
+/// Decode a URL-encoded value and append the result to the given Vector, passing
+/// the escapes of each vector through `hook`.
+#[inline(always)]
+pub(crate) unsafe fn url_decode_with<V: SimdVector, H: Hook<V>>(src: &[u8], dst: &mut Vec<u8>, hook: &mut H) {
+    let dst_start = dst.len();
+    dst.reserve_exact(src.len());
+
+    let len = decode_to_with(src, dst.as_mut_ptr().add(dst_start), dst.capacity() - dst_start, hook);
+    dst.set_len(dst_start + len);
+}
+
+/// Decode a URL-encoded value to `dst`, which has room for `capacity` bytes.
+///
+/// `capacity` must be at least `src.len()`. Returns the number of bytes written.
+#[inline(always)]
+pub(crate) unsafe fn decode_to<V: SimdVector>(src: &[u8], dst: *mut u8, capacity: usize) -> usize {
+    decode_to_with::<V, ()>(src, dst, capacity, &mut ())
+}
+
+/// Decode a URL-encoded value to `dst`, passing the escapes of each vector through
+/// `hook`.
+///
+/// Vectors are loaded at fixed offsets so that loads and classification don't depend
+/// on the previous iteration. An escape which spans two vectors is decoded in the
+/// first and its hex digits are skipped in the second.
+#[inline(always)]
+unsafe fn decode_to_with<V: SimdVector, H: Hook<V>>(src: &[u8], dst: *mut u8, capacity: usize, hook: &mut H) -> usize {
+    let lanes = V::LANES;
+    let start = src.as_ptr();
+    let mut src = src;
+
+    let mut dst_ptr = dst;
+
+    // Number of bytes at the start of the next vector which are hex digits of an
+    // escape that was decoded at the end of the previous vector.
+    let mut carry = 0;
+
+    // Load two vectors at a time. Each vector looks 2 bytes ahead to decode escapes
+    // that span the next vector.
+    while src.len() >= 2 * lanes + 2 {
+        let ptr = src.as_ptr();
+        let chunk1 = maybe_replace_plus::<V, H>(V::load(ptr));
+        let chunk2 = maybe_replace_plus::<V, H>(V::load(ptr.add(lanes)));
+        chunk1.print("chunk1");
+        chunk2.print("chunk2");
+
+        let percent1 = chunk1.cmpeq(V::splat(b'%'));
+        let percent2 = chunk2.cmpeq(V::splat(b'%'));
+
+        // Check if all bytes are 0, if so then there are no % symbols to decode.
+        if carry == 0 && percent1.or(percent2).is_zero() {
+            chunk1.store(dst_ptr);
+            chunk2.store(dst_ptr.add(lanes));
+            dst_ptr = dst_ptr.add(2 * lanes);
+            src = src.get_unchecked(2 * lanes..);
+            continue;
+        }
+
+        let offset = ptr.offset_from(start) as usize;
+        let (chunk1, found1) = decode_ahead(chunk1, percent1, V::load(ptr.add(1)), V::load(ptr.add(2)), offset, hook);
+        let (chunk2, found2) = decode_ahead(chunk2, percent2, V::load(ptr.add(lanes + 1)), V::load(ptr.add(lanes + 2)), offset + lanes, hook);
+
+        let (len1, carry1) = chunk1.compact_store(found1, carry, dst_ptr);
+        dst_ptr = dst_ptr.add(len1);
+        let (len2, carry2) = chunk2.compact_store(found2, carry1, dst_ptr);
+        dst_ptr = dst_ptr.add(len2);
+
+        carry = carry2;
+        src = src.get_unchecked(2 * lanes..);
+    }
+
+    // Decode the remaining bytes one vector at a time. Bytes past the end of the
+    // input are loaded as zeros. Zeros are never hex digits so escapes can't run
+    // past the end of the input.
+    while src.len() > carry {
+        let (chunk, next1, next2) = load_lookahead::<V>(src);
+        chunk.print("partial");
+
+        let chunk = maybe_replace_plus::<V, H>(chunk);
+        let percent = chunk.cmpeq(V::splat(b'%'));
+        let offset = src.as_ptr().offset_from(start) as usize;
+        let (chunk, found) = decode_ahead(chunk, percent, next1, next2, offset, hook);
+
+        // Padding is never removed so it is the last part of the output.
+        let end = cmp::min(src.len(), lanes);
+        let padding = lanes - end;
+
+        // Only write a whole vector if there is room for it in dst.
+        let written = dst_ptr.offset_from(dst) as usize;
+        let (len, next_carry) = if capacity - written >= lanes {
+            chunk.compact_store(found, carry, dst_ptr)
+        } else {
+            let mut buf = [0u8; MAX_LANES];
+            let (len, next_carry) = chunk.compact_store(found, carry, buf.as_mut_ptr());
+            std::ptr::copy_nonoverlapping(buf.as_ptr(), dst_ptr, len - padding);
+            (len, next_carry)
+        };
+        dst_ptr = dst_ptr.add(len - padding);
+
+        carry = next_carry;
+        src = src.get_unchecked(end..);
+    }
+
+    dst_ptr.offset_from(dst) as usize
+}
+
+/// Count the bytes that [`url_decode`] would output for `src`.
+///
+/// Each valid escape is 3 bytes of input and 1 byte of output, so only the escapes
+/// need to be counted.
+#[inline(always)]
+pub(crate) unsafe fn decoded_len<V: SimdVector>(src: &[u8]) -> usize {
+    let mut escapes = 0;
+
+    scan::<V>(src, |_, _, found| {
+        escapes += found.count_ones() as usize;
+        true
+    });
+
+    src.len() - 2 * escapes
+}
+
+/// Check if decoding `src` in the given mode would change it.
+///
+/// Returns as soon as a valid escape, or a `+` in [`Mode::Form`], is found.
+#[inline(always)]
+pub(crate) unsafe fn needs_decoding<V: SimdVector>(src: &[u8], mode: Mode) -> bool {
+    let mut found_any = false;
+
+    scan::<V>(src, |_, chunk, found| {
+        found_any = found != 0 || (mode == Mode::Form && !chunk.cmpeq(V::splat(b'+')).is_zero());
+        !found_any
+    });
+
+    found_any
+}
+
+/// Check that every `%` in `src` starts a valid escape, and optionally that every
+/// byte is allowed by RFC 3986.
+///
+/// Returns the first offending byte.
+#[inline(always)]
+pub(crate) unsafe fn validate<V: SimdVector>(src: &[u8], rules: &ValidationRules) -> Result<(), ValidationError> {
+    let mut result = Ok(());
+
+    scan::<V>(src, |offset, chunk, found| {
+        let invalid = chunk.cmpeq(V::splat(b'%')).movemask() & !found;
+
+        let disallowed = if rules.rfc3986_chars_only {
+            // Ignore the padding past the end of the input.
+            let len = cmp::min(src.len() - offset, V::LANES);
+            disallowed_bytes(chunk) & (u32::MAX >> (32 - len))
+        } else {
+            0
+        };
+
+        let bad = invalid | disallowed;
+        if bad == 0 {
+            return true;
+        }
+
+        let i = bad.trailing_zeros();
+        let kind = if invalid & (1 << i) != 0 {
+            ValidationErrorKind::InvalidEscape
+        } else {
+            ValidationErrorKind::DisallowedByte
+        };
+        result = Err(ValidationError::new(offset + i as usize, kind));
+        false
+    });
+
+    result
+}
+
+/// Find the bytes of `chunk` which RFC 3986 does not allow unescaped.
+#[inline(always)]
+unsafe fn disallowed_bytes<V: SimdVector>(chunk: V) -> u32 {
+    // Bytes from 0x80 are negative so they are not printable.
+    let printable = chunk.cmpgt(V::splat(b' ')).and(chunk.cmplt(V::splat(0x7f)));
+
+    let mut excluded = V::splat(0);
+    for &byte in validation::EXCLUDED {
+        excluded = excluded.or(chunk.cmpeq(V::splat(byte)));
+    }
+
+    !printable.movemask() | excluded.movemask()
+}
+
+/// Find the valid escapes in each `LANES` bytes of `src`.
+///
+/// Calls `f` with the offset of each chunk, the chunk itself with bytes past the end
+/// of `src` loaded as zeros, and a mask of the percent symbols which start a valid
+/// escape. Stops early if `f` returns `false`.
+#[inline(always)]
+pub(crate) unsafe fn scan<V: SimdVector>(src: &[u8], mut f: impl FnMut(usize, V, u32) -> bool) {
+    let mut offset = 0;
+
+    while offset < src.len() {
+        let (chunk, next1, next2) = load_lookahead::<V>(src.get_unchecked(offset..));
+        let percent = chunk.cmpeq(V::splat(b'%'));
+
+        // Skip classifying hex digits when there are no % symbols.
+        let found = if percent.is_zero() {
+            0
+        } else {
+            find_escapes(percent, next1, next2)
+        };
+
+        if !f(offset, chunk, found) {
+            return;
+        }
+        offset += V::LANES;
+    }
+}
+
+/// Find the percent symbols and valid escapes in the first `LANES` bytes of `src`.
+///
+/// Returns the number of bytes classified and a mask of each with one bit per byte.
+#[inline(always)]
+pub(crate) unsafe fn escape_masks<V: SimdVector>(src: &[u8]) -> (usize, u32, u32) {
+    let (chunk, next1, next2) = load_lookahead::<V>(src);
+    let percent = chunk.cmpeq(V::splat(b'%'));
+
+    (cmp::min(src.len(), V::LANES), percent.movemask(), find_escapes(percent, next1, next2))
+}
+
+/// Load the first `LANES` bytes of `src` and the bytes 1 and 2 positions later,
+/// padded with zeros.
+#[inline(always)]
+unsafe fn load_lookahead<V: SimdVector>(src: &[u8]) -> (V, V, V) {
+    let ptr = src.as_ptr();
+
+    if src.len() >= V::LANES + 2 {
+        (V::load(ptr), V::load(ptr.add(1)), V::load(ptr.add(2)))
+    } else if src.len() > V::LANES {
+        (V::load(ptr), load_ahead(src, 1), load_ahead(src, 2))
+    } else {
+        let chunk = V::load_partial(src);
+        (chunk, chunk.shift_down::<1>(), chunk.shift_down::<2>())
+    }
+}
+
+/// Load up to `LANES` bytes of `src` starting at `offset`, padded with zeros.
+#[inline(always)]
+unsafe fn load_ahead<V: SimdVector>(src: &[u8], offset: usize) -> V {
+    let start = cmp::min(offset, src.len());
+    let end = cmp::min(offset + V::LANES, src.len());
+    V::load_partial(src.get_unchecked(start..end))
+}
+
+/// Replace plus (+) with space if the hook decodes it.
+#[inline(always)]
+unsafe fn maybe_replace_plus<V: SimdVector, H: Hook<V>>(chunk: V) -> V {
+    if H::PLUS_AS_SPACE {
+        replace_plus(chunk)
+    } else {
+        chunk
+    }
+}
+
+/// Replace plus (+) with space.
+#[inline(always)]
+pub(crate) unsafe fn replace_plus<V: SimdVector>(chunk: V) -> V {
+    let found = chunk.cmpeq(V::splat(b'+'));
+    chunk.blend(V::splat(b' '), found)
+}
+
+/// Find the C0 control characters, 0x00 to 0x1F, in `chunk`.
+#[inline(always)]
+pub(crate) unsafe fn control_mask<V: SimdVector>(chunk: V) -> V {
+    // Comparisons are signed so bytes from 0x80 are below 0x20. Comparing with
+    // 0xff (-1) excludes them.
+    chunk.cmplt(V::splat(0x20)).and(chunk.cmpgt(V::splat(0xff)))
+}
+
+/// Find the percent symbols in `percent` which are followed by two hex digits.
+///
+/// `next1` and `next2` are the input loaded 1 and 2 bytes later.
+#[inline(always)]
+pub(crate) unsafe fn find_escapes<V: SimdVector>(percent: V, next1: V, next2: V) -> u32 {
+    let (valid1, _) = hex_digits(next1);
+    let (valid2, _) = hex_digits(next2);
+    percent.and(valid1.and(valid2)).movemask()
+}
+
+/// Decode the valid escapes in `chunk`.
+///
+/// `percent` marks the percent symbols in `chunk`. `next1` and `next2` are the
+/// input loaded 1 and 2 bytes later so that each position can see its hex digits.
+///
+/// The escapes are passed through `hook` with the `offset` of the chunk in the input.
+///
+/// Returns the chunk with each decoded escape in place of its percent symbol, and a
+/// mask of the decoded escapes. The hex digits are not removed.
+#[inline(always)]
+unsafe fn decode_ahead<V: SimdVector, H: Hook<V>>(chunk: V, percent: V, next1: V, next2: V, offset: usize, hook: &mut H) -> (V, u32) {
+    let (valid1, first) = hex_digits(next1);
+    let (valid2, second) = hex_digits(next2);
+    let found = percent.and(valid1.and(valid2));
+    found.print("found");
+
+    // Digits are at most 15 so shifting them doesn't spill into the next byte.
+    let hex = first.shl16::<4>().or(second);
+    let (hex, found) = hook.escapes(offset, hex, found);
+    let chunk = chunk.blend(hex, found);
+    chunk.print("hex");
+
+    (chunk, found.movemask())
+}
+
+/// Classify every byte of `chunk` as a hex digit.
+///
+/// Returns a mask of the hex digits and their values. Other bytes have the value 0.
+#[inline(always)]
+pub(crate) unsafe fn hex_digits<V: SimdVector>(chunk: V) -> (V, V) {
+    // Number hex
+    let digit_mask1 = chunk.cmplt(V::splat(b':')); // : is character after 9
+    let digit_mask2 = chunk.cmpgt(V::splat(b'/')); // / is character before 0
+    let number_mask = digit_mask1.and(digit_mask2);
+    let number = number_mask.and(chunk.sub(V::splat(b'0')));
+
+    // Zero the 6th bit (!0x20) to convert lowercase characters as uppercase
+    let upper = chunk.and(V::splat(0b11011111));
+
+    // Uppercase hex
+    let digit_mask1 = upper.cmplt(V::splat(b'G')); // G is character after F
+    let digit_mask2 = upper.cmpgt(V::splat(b'@')); // @ is character before A
+    let letter_mask = digit_mask1.and(digit_mask2);
+    let letter = letter_mask.and(upper.sub(V::splat(b'A' - 10)));
+
+    (number_mask.or(letter_mask), number.or(letter))
+}
+
+/// Indices of each byte in a 16 byte vector.
+const IOTA: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// [`SimdVector::compact_store`] for 16 byte vectors, using a lookup table of
+/// shuffles for each combination of escapes.
+#[inline(always)]
+pub(crate) unsafe fn compact_store_16<V: SimdVector>(chunk: V, found_mask: u32, carry: usize, dst: *mut u8) -> (usize, usize) {
+    debug_assert_eq!(16, V::LANES);
+
+    // Shifting the mask by `carry` skips the first bytes in the shuffle. Escapes at
+    // 14 and 15 don't need to be in the mask because their digits are at the end.
+    let index = (found_mask >> carry) & 0b0011111111111111;
+
+    // Instead of a map, we could swap the order of found_mask using _bswap64 and then
+    //  we can access some bit operations like find index of lowest set bit
+    //  and clear lowest set bit.
+
+    // Another possibility is the map could only contain a 1 when there is an increment.
+    // This increment can be used to derive the shuffle_mask eg 00010 -> 2,2,2,2,0.
+    let shuffle_mask = V::load(SHUFFLE_MASK.get_unchecked(index as usize).as_ptr());
+    shuffle_mask.print("shuffle_mask");
+
+    // Shuffle the output
+    let shuffle_map = V::load(IOTA.as_ptr()).add(shuffle_mask).add(V::splat(carry as u8));
+    shuffle_map.print("shuffle_map");
+
+    chunk.shuffle(shuffle_map).store(dst);
+
+    // Count number of valid percent symbols. An escape at 14 has 1 digit in
+    // this chunk and an escape at 15 has none.
+    let end14 = ((found_mask >> 14) & 1) as usize;
+    let end15 = ((found_mask >> 15) & 1) as usize;
+    let num_percent = (found_mask & 0b0011111111111111).count_ones() as usize;
+    let num_junk = carry + 2 * num_percent + end14;
+
+    (16 - num_junk, end14 + 2 * end15)
+}
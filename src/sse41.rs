@@ -1,188 +1,191 @@
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
-use std::mem;
+use std::ptr;
 
-use crate::fallback;
-use crate::shuffle_mask;
-
-use shuffle_mask::SHUFFLE_MASK;
+use crate::simd::{self, Hook, SimdVector};
+use crate::validation::{ValidationError, ValidationRules};
+use crate::Mode;
+use crate::swar;
 
 /// This is an SSE4.1 + POPCNT implementation of URL decode.
 ///
+/// The result is appended to `dst`, keeping any existing contents.
+///
 /// It requires SSE4.1 for `_mm_blendv_epi8` and `_mm_testz_si128`.
 ///
 /// No validation of UTF-8 data is performed so if a string is desired,
 /// it should be sanitised with eg [String::from_utf8_lossy]
 /// (https://doc.rust-lang.org/std/string/struct.String.html#method.from_utf8_lossy)
+///
+/// # Safety
+///
+/// The CPU must support SSE4.1 and POPCNT.
 #[target_feature(enable = "sse4.1")]
 #[target_feature(enable = "popcnt")]
 pub unsafe fn url_decode(src: &[u8], dst: &mut Vec<u8>) {
-    let mut src = src;
+    simd::url_decode::<__m128i>(src, dst)
+}
+
+/// Decode a URL-encoded value and append the result to `dst`, passing the escapes
+/// of each vector through `hook`.
+#[target_feature(enable = "sse4.1")]
+#[target_feature(enable = "popcnt")]
+pub(crate) unsafe fn url_decode_with<H: Hook<__m128i>>(src: &[u8], dst: &mut Vec<u8>, hook: &mut H) {
+    simd::url_decode_with(src, dst, hook)
+}
+
+/// Decode a URL-encoded value to `dst`, which has room for `capacity` bytes.
+///
+/// `capacity` must be at least `src.len()`. Returns the number of bytes written.
+#[target_feature(enable = "sse4.1")]
+#[target_feature(enable = "popcnt")]
+pub(crate) unsafe fn decode_to(src: &[u8], dst: *mut u8, capacity: usize) -> usize {
+    simd::decode_to::<__m128i>(src, dst, capacity)
+}
+
+/// Count the bytes that [`url_decode`] would output for `src` without decoding it.
+///
+/// # Safety
+///
+/// The CPU must support SSE4.1 and POPCNT.
+#[target_feature(enable = "sse4.1")]
+#[target_feature(enable = "popcnt")]
+pub unsafe fn decoded_len(src: &[u8]) -> usize {
+    simd::decoded_len::<__m128i>(src)
+}
+
+/// Check if decoding `src` in the given mode would change it.
+///
+/// # Safety
+///
+/// The CPU must support SSE4.1.
+#[target_feature(enable = "sse4.1")]
+pub unsafe fn needs_decoding(src: &[u8], mode: Mode) -> bool {
+    simd::needs_decoding::<__m128i>(src, mode)
+}
+
+/// Check that every `%` in `src` starts a valid escape, and optionally that every
+/// byte is allowed by RFC 3986.
+///
+/// # Safety
+///
+/// The CPU must support SSE4.1.
+#[target_feature(enable = "sse4.1")]
+pub unsafe fn validate(src: &[u8], rules: &ValidationRules) -> Result<(), ValidationError> {
+    simd::validate::<__m128i>(src, rules)
+}
 
-    let mut dst_len = dst.len();
-    dst.reserve_exact(src.len());
-    let mut dst_ptr = dst.as_mut_ptr().add(dst_len);
+/// Find the percent symbols and valid escapes in the first 16 bytes of `src`.
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn escape_masks(src: &[u8]) -> (usize, u32, u32) {
+    simd::escape_masks::<__m128i>(src)
+}
 
-    let byte_plus = _mm_set1_epi8(b'+' as i8);
-    let byte_space = _mm_set1_epi8(b' ' as i8);
-    let byte_percent = _mm_set1_epi8(b'%' as i8);
+impl SimdVector for __m128i {
+    const LANES: usize = 16;
+
+    #[inline(always)]
+    unsafe fn splat(byte: u8) -> Self {
+        _mm_set1_epi8(byte as i8)
+    }
 
-    // Load chunks of 16 bytes of data at a time.
-    while src.len() >= 16 {
-        // Load data from unaligned address.
+    #[inline(always)]
+    unsafe fn load(ptr: *const u8) -> Self {
         // TODO: is this notably slower than loading from an aligned address?
-        let chunk = _mm_loadu_si128(src.as_ptr() as *const __m128i);
-        print_m128i!("chunk", chunk);
-
-        // Replace plus (+) with space
-        let found = _mm_cmpeq_epi8(chunk, byte_plus);
-        print_m128i!("found+", found);
-        let chunk = _mm_blendv_epi8(chunk, byte_space, found);
-        print_m128i!("chunk+", chunk);
-
-        // Locate percent symbol
-        let found = _mm_cmpeq_epi8(chunk, byte_percent);
-        let found = _mm_and_si128(found, _mm_xor_si128(found, _mm_srli_si128(found, 1)));
-        let found = _mm_and_si128(found, _mm_xor_si128(found, _mm_srli_si128(found, 2)));
-        print_m128i!("found", found);
-
-        // Check if all bytes are 0, if so then there are no % or + symbols.
-        if _mm_testz_si128(found, found) > 0 {
-            _mm_storeu_si128(dst_ptr as *mut __m128i, chunk);
-            dst_ptr = dst_ptr.offset(16);
-            dst_len += 16;
-            src = &src.get_unchecked(16..);
-            continue;
-        }
+        _mm_loadu_si128(ptr as *const __m128i)
+    }
 
-        // Find the next 2 bytes
-
-        let mask1 = _mm_slli_si128(found, 1);
-        print_m128i!("mask1", mask1);
-        let first1 = _mm_and_si128(chunk, mask1);
-        print_m128i!("first1", first1);
-
-        // Using `found` allows us to not depend on mask1
-        let mask2 = _mm_slli_si128(found, 2);
-        print_m128i!("mask2", mask2);
-        let second1 = _mm_and_si128(chunk, mask2);
-        print_m128i!("second1", second1);
-
-        // Decode hex
-
-        let first_and_second = _mm_or_si128(first1, second1);
-
-        // Number hex
-        let byte_zero = _mm_set1_epi8(b'0' as i8);
-        let digit_mask1 = _mm_cmplt_epi8(first_and_second, _mm_set1_epi8(b':' as i8)); // : is character after 9
-        let digit_mask2 = _mm_cmpgt_epi8(first_and_second, _mm_set1_epi8(b'/' as i8)); // / is character before 0
-        let digit_mask = _mm_and_si128(digit_mask1, digit_mask2);
-        let first_part1 = _mm_and_si128(digit_mask, _mm_sub_epi8(first_and_second, byte_zero));
-        let valid_mask = digit_mask;
-        print_m128i!("digit_mask1", digit_mask);
-        print_m128i!("first1-1", first_part1);
-
-        // Zero the 6th bit (!0x20) to convert lowercase characters as uppercase
-        let lower_mask = _mm_set1_epi8(0b11011111u8 as i8);
-        let first_and_second = _mm_and_si128(first_and_second, lower_mask);
-        print_m128i!("first_and_second", first_and_second);
-
-        // Uppercase hex
-        let byte_upper = _mm_set1_epi8(b'A' as i8 - 10);
-        let digit_mask1 = _mm_cmplt_epi8(first_and_second, _mm_set1_epi8(b'G' as i8)); // G is character after F
-        let digit_mask2 = _mm_cmpgt_epi8(first_and_second, _mm_set1_epi8(b'@' as i8)); // @ is character before A
-        let digit_mask = _mm_and_si128(digit_mask1, digit_mask2);
-        let first_part2 = _mm_and_si128(digit_mask, _mm_sub_epi8(first_and_second, byte_upper));
-        let valid_mask = _mm_or_si128(valid_mask, digit_mask);
-        print_m128i!("digit_mask2", digit_mask);
-        print_m128i!("first1-2", first_part2);
-
-        // Check that both digits are valid
-        let valid_mask = _mm_and_si128(valid_mask, _mm_slli_si128(valid_mask, 1));
-        let valid_mask = _mm_or_si128(valid_mask, _mm_srli_si128(valid_mask, 1));
-        let valid_mask = _mm_or_si128(valid_mask, _mm_srli_si128(valid_mask, 1));
-        print_m128i!("valid_mask", valid_mask);
-        let found = _mm_and_si128(valid_mask, found);
-        print_m128i!("found2", found);
-
-        // Merge first hex digit transforms
-        let first_and_second = _mm_or_si128(first_part1, first_part2);
-        let first_and_second = _mm_and_si128(valid_mask, first_and_second);
-
-        // Note: I really want a `<< 4` for epi8 but it doesn't exist :(
-        // This is ok because valid first digits have a spare byte on each side.
-        let first1 = _mm_slli_epi16(_mm_and_si128(mask1, first_and_second), 4);
-        let first1 = _mm_and_si128(first1, mask1);
-        print_m128i!("first1-merged", first1);
-
-        // Second hex digit
-        let second1 = _mm_srli_si128(_mm_and_si128(first_and_second, mask2), 1);
-
-        // Merge hex digits into place and position where the percent was
-        let hex = _mm_or_si128(first1, second1);
-        let hex = _mm_srli_si128(hex, 1);
-        let hex = _mm_and_si128(hex, found);
-        print_m128i!("hex", hex);
-
-        // Squash hex and original data together with mask
-        let hex = _mm_blendv_epi8(chunk, hex, found);
-        print_m128i!("chunk2", chunk);
-        print_m128i!("found2", found);
-        print_m128i!("hex2", hex);
-        // Reduce 16 bytes to 16 bits for ease of use
-        let found_mask = _mm_movemask_epi8(found) as u32;
-
-        // Count number of valid percent symbols. These are represented as a 1 in found_mask.
-        let num_percent = _popcnt32(found_mask as i32) as usize;
-        let num_junk = 2 * num_percent;
-
-        // Shave off the right two bits as they are always 0 or irelevant
-        let found_mask = found_mask & 0b0011111111111111;
-
-        // Instead of a map, we could swap the order of found_mask using _bswap64 and then
-        //  we can access some bit operations like find index of lowest set bit
-        //  and clear lowest set bit.
-
-        // Another possibility is the map could only contain a 1 when there is an increment.
-        // This increment can be used to derive the shuffle_mask eg 00010 -> 2,2,2,2,0.
-        let shuffle_mask = SHUFFLE_MASK.get_unchecked(found_mask as usize);
-        let shuffle_mask = mem::transmute(*shuffle_mask);
-        print_m128i!("shuffle_mask", shuffle_mask);
-
-        // Calculate number of bits to re-process next time.
-        // This is because we end in % or %X and can't decode bytes that aren't in the chunk.
-
-        let mut shift_next = 0;
-        if src[14] == b'%' {
-            shift_next += 2;
-        } else if src[15] == b'%' {
-            shift_next += 1;
+    #[inline(always)]
+    unsafe fn load_partial(src: &[u8]) -> Self {
+        if src.len() > 8 {
+            let lo = u64::from_le_bytes(ptr::read_unaligned(src.as_ptr() as *const [u8; 8]));
+            let hi = swar::load_partial(src.get_unchecked(8..));
+            _mm_set_epi64x(hi as i64, lo as i64)
+        } else {
+            _mm_set_epi64x(0, swar::load_partial(src) as i64)
         }
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut u8) {
+        _mm_storeu_si128(ptr as *mut __m128i, self)
+    }
+
+    #[inline(always)]
+    unsafe fn cmpeq(self, other: Self) -> Self {
+        _mm_cmpeq_epi8(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn cmpgt(self, other: Self) -> Self {
+        _mm_cmpgt_epi8(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn cmplt(self, other: Self) -> Self {
+        _mm_cmplt_epi8(self, other)
+    }
 
-        let src_end: usize = 16 - shift_next;
-        let dst_end: usize = src_end - num_junk;
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self {
+        _mm_and_si128(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self {
+        _mm_or_si128(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        _mm_add_epi8(self, other)
+    }
 
-        // Shuffle the output
-        let plain_shuffle_map = _mm_set_epi8(15,14,13,12,11,10,9,8,7,6,5,4,3,2,1,0);
-        let shuffle_map = _mm_add_epi8(plain_shuffle_map, shuffle_mask);
-        print_m128i!("shuffle_map", shuffle_map);
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        _mm_sub_epi8(self, other)
+    }
 
-        let hex = _mm_shuffle_epi8(hex, shuffle_map);
+    #[inline(always)]
+    unsafe fn blend(self, other: Self, mask: Self) -> Self {
+        _mm_blendv_epi8(self, other, mask)
+    }
 
-        // Copy to dst
-        _mm_storeu_si128(dst_ptr as *mut __m128i, hex);
-        dst_ptr = dst_ptr.offset(dst_end as isize);
-        dst_len += dst_end;
+    #[inline(always)]
+    unsafe fn shl16<const BITS: i32>(self) -> Self {
+        _mm_slli_epi16::<BITS>(self)
+    }
 
-        // Advance
-        src = &src.get_unchecked(src_end..);
+    #[inline(always)]
+    unsafe fn shift_down<const BYTES: i32>(self) -> Self {
+        _mm_srli_si128::<BYTES>(self)
     }
 
-    dst.set_len(dst_len);
+    #[inline(always)]
+    unsafe fn shuffle(self, indices: Self) -> Self {
+        _mm_shuffle_epi8(self, indices)
+    }
+
+    #[inline(always)]
+    unsafe fn movemask(self) -> u32 {
+        _mm_movemask_epi8(self) as u32
+    }
+
+    #[inline(always)]
+    unsafe fn is_zero(self) -> bool {
+        _mm_testz_si128(self, self) > 0
+    }
+
+    /// Compacts using a lookup table of shuffles for each combination of escapes.
+    #[inline(always)]
+    unsafe fn compact_store(self, found_mask: u32, carry: usize, dst: *mut u8) -> (usize, usize) {
+        simd::compact_store_16(self, found_mask, carry, dst)
+    }
 
-    if src.len() > 0 {
-        fallback::decode_extend(src, dst);
+    #[inline(always)]
+    unsafe fn print(self, _msg: &str) {
+        print_m128i!(_msg, self);
     }
 }
 
@@ -190,7 +193,17 @@ pub unsafe fn url_decode(src: &[u8], dst: &mut Vec<u8>) {
 mod tests {
     #![allow(non_snake_case)]
 
-    use super::url_decode;
+    use super::{url_decode, decoded_len, needs_decoding, validate};
+    use crate::test_util::{assert_decoded_len, assert_matches_fallback, assert_needs_decoding, assert_validate, Backend};
+
+    fn backend() -> Backend {
+        Backend {
+            url_decode: |src, dst| unsafe { url_decode(src, dst) },
+            decoded_len: |src| unsafe { decoded_len(src) },
+            needs_decoding: |src, mode| unsafe { needs_decoding(src, mode) },
+            validate: |src, rules| unsafe { validate(src, rules) },
+        }
+    }
 
     #[test]
     fn url_decode_space() {
@@ -342,4 +355,100 @@ mod tests {
         unsafe { url_decode(v, &mut result) };
         assert_eq!(b"\xCF%%sA\x00`A%5%%6%6\xEF", &result[..]);
     }
+
+    #[test]
+    fn test_append() {
+        let mut result = b"abc".to_vec();
+
+        let v = b"%41a%42b12345678%43";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"abcAaBb12345678C", &result[..]);
+    }
+
+    #[test]
+    fn test_short() {
+        let mut result = Vec::new();
+
+        let v = b"%41";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"A", &result[..]);
+
+        let v = b"a+b%4";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"a b%4", &result[..]);
+
+        let v = b"aaaaaaaaaaaaa%4";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaa%4", &result[..]);
+
+        let v = b"";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"", &result[..]);
+    }
+
+    #[test]
+    fn test_tail() {
+        let mut result = Vec::new();
+
+        // last char of block is % and is decoded in the tail
+        let v = b"aaaaaaaaaaaaaaa%41";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaA", &result[..]);
+
+        let v = b"aaaaaaaaaaaaaaaa%41+%4";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaaA %4", &result[..]);
+    }
+
+    #[test]
+    fn test_carry() {
+        let mut result = Vec::new();
+
+        // escape at the 15th byte of the first chunk
+        let v = b"aaaaaaaaaaaaaa%41aaaaaaaaaaaaaaaaaaa";
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaAaaaaaaaaaaaaaaaaaaa", &result[..]);
+
+        // escape at the last byte of the first chunk
+        let v = b"aaaaaaaaaaaaaaa%41aaaaaaaaaaaaaaaaaa";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaAaaaaaaaaaaaaaaaaaa", &result[..]);
+
+        // escape at the last byte of the second chunk
+        let v = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%41aaaaaaaaaaaaaaaa";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaAaaaaaaaaaaaaaaaa", &result[..]);
+
+        // carried digits followed by an escape
+        let v = b"aaaaaaaaaaaaaaa%41%42aaaaaaaaaaaaaaaa%4";
+        result.clear();
+        unsafe { url_decode(v, &mut result) };
+        assert_eq!(b"aaaaaaaaaaaaaaaABaaaaaaaaaaaaaaaa%4", &result[..]);
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        assert_matches_fallback(backend(), 0..100, 100);
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        assert_decoded_len(backend());
+    }
+
+    #[test]
+    fn test_needs_decoding() {
+        assert_needs_decoding(backend());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_validate(backend());
+    }
 }
@@ -0,0 +1,594 @@
+//! A portable SWAR (SIMD within a register) implementation of URL decode.
+//!
+//! Input is processed 8 bytes at a time in a `u64` so it does not depend on any
+//! particular CPU instructions. It is used when no SIMD implementation is compiled in.
+
+use std::cmp;
+use std::ptr;
+
+use crate::validation::{self, ValidationError, ValidationErrorKind, ValidationRules};
+use crate::Mode;
+
+const ONES: u64 = 0x0101_0101_0101_0101;
+const HIGH: u64 = 0x8080_8080_8080_8080;
+const LOW7: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+
+/// Repeat `byte` in every byte of a word.
+#[inline(always)]
+const fn splat(byte: u8) -> u64 {
+    ONES * byte as u64
+}
+
+/// Set the high bit of every byte in `word` that equals `byte`.
+#[inline(always)]
+fn eq_mask(word: u64, byte: u8) -> u64 {
+    let x = word ^ splat(byte);
+    !(((x & LOW7) + LOW7) | x) & HIGH
+}
+
+/// Set the high bit of every byte in `word` that is within `lo..=hi`.
+///
+/// Both `lo` and `hi` must be ASCII.
+#[inline(always)]
+fn range_mask(word: u64, lo: u8, hi: u8) -> u64 {
+    // Adding to the low 7 bits can't carry into the next byte.
+    let x = word & LOW7;
+    let ge_lo = x + splat(0x80 - lo);
+    let gt_hi = x + splat(0x7f - hi);
+    ge_lo & !gt_hi & !word & HIGH
+}
+
+/// Set the high bit of every byte in `word` that is a hex digit.
+#[inline(always)]
+fn hex_mask(word: u64) -> u64 {
+    // Setting the 6th bit (0x20) converts uppercase characters to lowercase.
+    range_mask(word, b'0', b'9') | range_mask(word | splat(0x20), b'a', b'f')
+}
+
+/// Convert every byte of `word` to the value of the hex digit it would represent.
+///
+/// The result is meaningless for bytes which are not hex digits.
+#[inline(always)]
+fn hex_values(word: u64) -> u64 {
+    // Letters have the 7th bit (0x40) set and their low nibble is 1 for a/A.
+    (word & splat(0x0f)) + ((word >> 6) & ONES) * 9
+}
+
+/// Replace b'+' with b' '
+#[inline(always)]
+fn replace_plus(word: u64) -> u64 {
+    word ^ ((eq_mask(word, b'+') >> 7) * (b'+' ^ b' ') as u64)
+}
+
+/// Decode a URL-encoded value and append the result to the given Vector.
+///
+/// This processes 8 bytes at a time using ordinary integer instructions so it is
+/// available on every architecture.
+pub fn url_decode(src: &[u8], dst: &mut Vec<u8>) {
+    url_decode_with(src, dst, &mut ())
+}
+
+/// Inspects or changes how each escape is decoded.
+pub(crate) trait Hook {
+    /// Whether `+` is decoded to a space.
+    const PLUS_AS_SPACE: bool = true;
+
+    /// Called before the escape at `offset` in the input is decoded to `byte`.
+    ///
+    /// Returns the byte to write, or `None` to keep the escape as it is.
+    #[inline(always)]
+    fn escape(&mut self, _offset: usize, byte: u8) -> Option<u8> {
+        Some(byte)
+    }
+}
+
+/// Decode as [`url_decode`].
+impl Hook for () {}
+
+/// Decode a URL-encoded value and append the result to `dst`, passing each escape
+/// through `hook`.
+#[inline(always)]
+pub(crate) fn url_decode_with<H: Hook>(src: &[u8], dst: &mut Vec<u8>, hook: &mut H) {
+    let dst_len = dst.len();
+    dst.reserve_exact(src.len());
+
+    unsafe {
+        let len = decode_to_with(src, dst.as_mut_ptr().add(dst_len), hook);
+        dst.set_len(dst_len + len);
+    }
+}
+
+/// Decode a URL-encoded value to `dst`, which must have room for `src.len()` bytes.
+///
+/// Returns the number of bytes written.
+pub(crate) unsafe fn decode_to(src: &[u8], dst: *mut u8) -> usize {
+    decode_to_with(src, dst, &mut ())
+}
+
+/// Decode a URL-encoded value to `dst`, passing each escape through `hook`.
+#[inline(always)]
+unsafe fn decode_to_with<H: Hook>(src: &[u8], dst: *mut u8, hook: &mut H) -> usize {
+    let mut offset = 0;
+    let mut dst_ptr = dst;
+
+    // Load chunks of 8 bytes of data at a time.
+    while src.len() - offset >= 8 {
+        let word = u64::from_le_bytes(ptr::read_unaligned(src.as_ptr().add(offset) as *const [u8; 8]));
+        offset += decode_word(word, 8, offset, &mut dst_ptr, hook);
+    }
+
+    // Pad the remainder with zeros which are never part of a valid escape.
+    if offset < src.len() {
+        let rest = src.get_unchecked(offset..);
+        decode_word(load_partial(rest), rest.len(), offset, &mut dst_ptr, hook);
+    }
+
+    dst_ptr.offset_from(dst) as usize
+}
+
+/// Count the bytes that [`url_decode`] would output for `src` without decoding it.
+pub fn decoded_len(src: &[u8]) -> usize {
+    let mut escapes = 0;
+
+    scan(src, |_, _, found| {
+        escapes += found.count_ones() as usize;
+        true
+    });
+
+    src.len() - 2 * escapes
+}
+
+/// Check if decoding `src` in the given mode would change it.
+///
+/// Returns as soon as a valid escape, or a `+` in [`Mode::Form`], is found.
+pub fn needs_decoding(src: &[u8], mode: Mode) -> bool {
+    let mut found_any = false;
+
+    scan(src, |_, word, found| {
+        found_any = found != 0 || (mode == Mode::Form && eq_mask(word, b'+') != 0);
+        !found_any
+    });
+
+    found_any
+}
+
+/// Check that every `%` in `src` starts a valid escape, and optionally that every
+/// byte is allowed by RFC 3986.
+///
+/// Returns the first offending byte.
+pub fn validate(src: &[u8], rules: &ValidationRules) -> Result<(), ValidationError> {
+    let mut result = Ok(());
+
+    scan(src, |offset, word, found| {
+        let invalid = eq_mask(word, b'%') & !found;
+
+        let disallowed = if rules.rfc3986_chars_only {
+            // Ignore the padding past the end of the input.
+            let len = cmp::min(src.len() - offset, 8);
+            disallowed_bytes(word) & (HIGH >> (8 * (8 - len)))
+        } else {
+            0
+        };
+
+        let bad = invalid | disallowed;
+        if bad == 0 {
+            return true;
+        }
+
+        let i = bad.trailing_zeros() / 8;
+        let kind = if invalid & (0x80 << (8 * i)) != 0 {
+            ValidationErrorKind::InvalidEscape
+        } else {
+            ValidationErrorKind::DisallowedByte
+        };
+        result = Err(ValidationError::new(offset + i as usize, kind));
+        false
+    });
+
+    result
+}
+
+/// Set the high bit of every byte in `word` which RFC 3986 does not allow unescaped.
+#[inline(always)]
+fn disallowed_bytes(word: u64) -> u64 {
+    let mut excluded = 0;
+    for &byte in validation::EXCLUDED {
+        excluded |= eq_mask(word, byte);
+    }
+
+    (!range_mask(word, b'!', b'~') & HIGH) | excluded
+}
+
+/// Find the valid escapes in each 8 bytes of `src`.
+///
+/// Calls `f` with the offset of each word, the word itself padded with zeros past the
+/// end of `src`, and a word with the high bit set for each percent symbol which starts
+/// a valid escape. Stops early if `f` returns `false`.
+#[inline(always)]
+pub(crate) fn scan(src: &[u8], mut f: impl FnMut(usize, u64, u64) -> bool) {
+    let mut offset = 0;
+    let mut word = load_word(src, 0);
+
+    while offset < src.len() {
+        let next = load_word(src, offset + 8);
+        let percent = eq_mask(word, b'%');
+
+        let found = if percent == 0 { 0 } else { find_escapes(percent, word, next) };
+
+        if !f(offset, word, found) {
+            return;
+        }
+
+        word = next;
+        offset += 8;
+    }
+}
+
+/// Find the percent symbols and valid escapes in the first 8 bytes of `src`.
+///
+/// Returns the number of bytes classified and a mask of each with one bit per byte.
+pub(crate) fn escape_masks(src: &[u8]) -> (usize, u32, u32) {
+    let word = load_word(src, 0);
+    let percent = eq_mask(word, b'%');
+    let found = find_escapes(percent, word, load_word(src, 8));
+
+    (cmp::min(src.len(), 8), movemask(percent), movemask(found))
+}
+
+/// Find the percent symbols in `percent` which are followed by two hex digits,
+/// looking into the `next` word.
+#[inline(always)]
+fn find_escapes(percent: u64, word: u64, next: u64) -> u64 {
+    // Byte n is set if bytes n+1 and n+2 are hex digits.
+    let hex = hex_mask(word);
+    let next_hex = hex_mask(next);
+    percent & (hex >> 8 | next_hex << 56) & (hex >> 16 | next_hex << 48)
+}
+
+/// Gather the high bit of every byte into the low 8 bits.
+#[inline(always)]
+fn movemask(mask: u64) -> u32 {
+    // Each bit is multiplied into a distinct position of the top byte.
+    ((mask >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56) as u32
+}
+
+/// Load the 8 bytes of `src` at `offset`, padded with zeros past the end.
+#[inline(always)]
+fn load_word(src: &[u8], offset: usize) -> u64 {
+    if offset + 8 <= src.len() {
+        unsafe { u64::from_le_bytes(ptr::read_unaligned(src.as_ptr().add(offset) as *const [u8; 8])) }
+    } else {
+        load_partial(src.get(offset..).unwrap_or(&[]))
+    }
+}
+
+/// Load up to 8 bytes into a word, padded with zeros.
+///
+/// This avoids a call to `memcpy` for short inputs by using overlapping loads.
+#[inline(always)]
+pub(crate) fn load_partial(src: &[u8]) -> u64 {
+    let len = src.len();
+    debug_assert!(len <= 8);
+
+    if len >= 4 {
+        let lo = u32::from_le_bytes([src[0], src[1], src[2], src[3]]) as u64;
+        let hi = u32::from_le_bytes([src[len - 4], src[len - 3], src[len - 2], src[len - 1]]) as u64;
+        lo | (hi << (8 * (len - 4)))
+    } else if len > 0 {
+        // Covers 1, 2 and 3 bytes, reading some bytes more than once.
+        let mid = len / 2;
+        src[0] as u64 | (src[mid] as u64) << (8 * mid) | (src[len - 1] as u64) << (8 * (len - 1))
+    } else {
+        0
+    }
+}
+
+/// Decode the first `len` bytes of `word`, writing the result to `dst_ptr` and
+/// advancing it.
+///
+/// Returns the number of bytes consumed. When `len` is 8, a `%` in the last two
+/// bytes is not consumed because its hex digits are in the next word. Otherwise
+/// `word` is the end of the input and all `len` bytes are consumed.
+///
+/// Each escape is passed through `hook` with its position in the input, where
+/// `offset` is the position of the word.
+///
+/// At least 8 bytes must be writable at `dst_ptr` if `len` is 8, else `len` bytes.
+#[inline(always)]
+unsafe fn decode_word<H: Hook>(word: u64, len: usize, offset: usize, dst_ptr: &mut *mut u8, hook: &mut H) -> usize {
+    let word = if H::PLUS_AS_SPACE { replace_plus(word) } else { word };
+    let mut percent = eq_mask(word, b'%');
+
+    if percent == 0 {
+        if len == 8 {
+            ptr::write_unaligned(*dst_ptr as *mut [u8; 8], word.to_le_bytes());
+        } else {
+            ptr::copy_nonoverlapping(word.to_le_bytes().as_ptr(), *dst_ptr, len);
+        }
+        *dst_ptr = dst_ptr.add(len);
+        return len;
+    }
+
+    // Byte n is set if bytes n and n+1 are both hex digits.
+    let hex = hex_mask(word);
+    let valid = hex & (hex >> 8);
+
+    // Byte n holds the value of hex digits n and n+1 combined.
+    let values = hex_values(word);
+    let decoded = ((values << 4) | (values >> 8)).to_le_bytes();
+
+    let bytes = word.to_le_bytes();
+    let mut start = 0;
+    let mut end = len;
+
+    while percent != 0 {
+        let i = (percent.trailing_zeros() / 8) as usize;
+        // We end in % or %X and can't decode bytes that aren't in the word.
+        if len == 8 && i > 5 {
+            end = i;
+            break;
+        }
+        percent &= percent - 1;
+
+        ptr::copy_nonoverlapping(bytes.as_ptr().add(start), *dst_ptr, i - start);
+        *dst_ptr = dst_ptr.add(i - start);
+
+        let byte = if valid & (0x80 << (8 * (i + 1))) != 0 {
+            hook.escape(offset + i, decoded[i + 1])
+        } else {
+            None
+        };
+
+        match byte {
+            Some(byte) => {
+                **dst_ptr = byte;
+                start = i + 3;
+            }
+            None => {
+                **dst_ptr = b'%';
+                start = i + 1;
+            }
+        }
+        *dst_ptr = dst_ptr.add(1);
+    }
+
+    ptr::copy_nonoverlapping(bytes.as_ptr().add(start), *dst_ptr, end - start);
+    *dst_ptr = dst_ptr.add(end - start);
+
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    use super::{url_decode, decoded_len, needs_decoding, validate, escape_masks, eq_mask, hex_mask, hex_values, load_partial, splat};
+    use crate::test_util::{assert_decoded_len, assert_matches_fallback, assert_needs_decoding, assert_validate, Backend};
+
+    fn backend() -> Backend {
+        Backend { url_decode, decoded_len, needs_decoding, validate }
+    }
+
+    #[test]
+    fn test_masks() {
+        let word = u64::from_le_bytes(*b"%0aF+g/:");
+
+        assert_eq!(eq_mask(word, b'%'), 0x80);
+        assert_eq!(eq_mask(word, b'+'), 0x80 << 32);
+        assert_eq!(hex_mask(word), 0x8080_8080 & !0x80);
+        assert_eq!(hex_values(word) & 0xffff_ff00, 0x0f_0a_00_00);
+        assert_eq!(eq_mask(splat(0x80), 0x00), 0);
+        assert_eq!(hex_mask(splat(0xb0)), 0);
+    }
+
+    #[test]
+    fn test_escape_masks() {
+        assert_eq!((0, 0, 0), escape_masks(b""));
+        assert_eq!((3, 0b1, 0b1), escape_masks(b"%41"));
+        assert_eq!((8, 0b1100_0001, 0b1000_0001), escape_masks(b"%41aaa%%4142"));
+        assert_eq!((8, 0b1000_0000, 0), escape_masks(b"aaaaaaa%4"));
+    }
+
+    #[test]
+    fn test_load_partial() {
+        let v = b"12345678";
+
+        for len in 0..=8 {
+            let mut expected = [0u8; 8];
+            expected[..len].copy_from_slice(&v[..len]);
+            assert_eq!(load_partial(&v[..len]), u64::from_le_bytes(expected));
+        }
+    }
+
+    #[test]
+    fn url_decode_space() {
+        let v = b"%20\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b" \0\0\0\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_A() {
+        let v = b"%41\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"A\0\0\0\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AB() {
+        let v = b"%41%42\0\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"AB\0\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AaBb() {
+        let v = b"%41a%42b\0\0\0\0\0\0\0\0\0";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"AaBb\0\0\0\0\0\0\0\0\0", &result[..])
+    }
+
+    #[test]
+    fn url_decode_AaBb_numbers() {
+        let v = b"%41a%42b12345678";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"AaBb12345678", &result[..])
+    }
+
+    #[test]
+    fn url_decode_upper_hex_KaLb_numbers() {
+        let v = b"%4Ba%4Cb12345678";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"KaLb12345678", &result[..])
+    }
+
+    #[test]
+    fn url_decode_lower_hex_KaLb_numbers() {
+        let v = b"%4ba%4cb12345678";
+        let mut result = Vec::new();
+
+        url_decode(v, &mut result);
+        assert_eq!(b"KaLb12345678", &result[..])
+    }
+
+    #[test]
+    fn test_decode_invalid_chars() {
+        let mut result = Vec::new();
+
+        let v = b"%%12345678901234";
+        url_decode(v, &mut result);
+        assert_eq!(b"%\x12345678901234", &result[..]);
+
+        let v = b"%1%2345678901234";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"%1\x2345678901234", &result[..]);
+
+        let v = b"%%%1234567890123";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"%%\x1234567890123", &result[..]);
+
+        let v = b"%-12345678901234";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"%-12345678901234", &result[..]);
+
+        let v = b"%1-2345678901234";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"%1-2345678901234", &result[..]);
+    }
+
+    #[test]
+    fn test_end_percent() {
+        let mut result = Vec::new();
+
+        // last char of word is %
+        let v = b"aaaaaaa%";
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaaa%", &result[..]);
+
+        // 2nd last char of word is %
+        let v = b"aaaaaa%a";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaa%a", &result[..]);
+
+        // last char of tail is %
+        let v = b"aaaaaaaaaaa%";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaaaaaaa%", &result[..]);
+    }
+
+    #[test]
+    fn test_split_percent() {
+        let mut result = Vec::new();
+
+        // last char of word is %
+        let v = b"aaaaaaa%aaaaaaaa";
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaaa\xAAaaaaaa", &result[..]);
+
+        // 2nd last char of word is %
+        let v = b"aaaaaa%aaaaaaaaa";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaa\xAAaaaaaaa", &result[..]);
+
+        // escape split between word and tail
+        let v = b"aaaaaa%aa";
+        result.clear();
+        url_decode(v, &mut result);
+        assert_eq!(b"aaaaaa\xAA", &result[..]);
+    }
+
+    #[test]
+    fn test_out_of_ascii_hex() {
+        let mut result = Vec::new();
+
+        let v = b"%AAaaaaaaaaaaaaa";
+        url_decode(v, &mut result);
+        assert_eq!(b"\xAAaaaaaaaaaaaaa", &result[..]);
+    }
+
+    #[test]
+    fn test_replace_plus() {
+        let mut result = Vec::new();
+
+        let v = b"a+a+a+a+a+a+a+a+";
+        url_decode(v, &mut result);
+        assert_eq!(b"a a a a a a a a ", &result[..]);
+    }
+
+    #[test]
+    fn test_random_junk() {
+        let mut result = Vec::new();
+
+        let v = b"\xCF%%sA\x00`A%5%%6%6\xEF";
+        url_decode(v, &mut result);
+        assert_eq!(b"\xCF%%sA\x00`A%5%%6%6\xEF", &result[..]);
+    }
+
+    #[test]
+    fn test_matches_fallback() {
+        assert_matches_fallback(backend(), 0..40, 200);
+    }
+
+    #[test]
+    fn test_append() {
+        let mut result = b"abc".to_vec();
+
+        let v = b"%41a%42b12345678%43";
+        url_decode(v, &mut result);
+        assert_eq!(b"abcAaBb12345678C", &result[..]);
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        assert_decoded_len(backend());
+    }
+
+    #[test]
+    fn test_needs_decoding() {
+        assert_needs_decoding(backend());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_validate(backend());
+    }
+}
@@ -0,0 +1,119 @@
+//! Helpers shared by the tests which compare against the fallback.
+
+use std::ops::Range;
+
+use crate::validation::{ValidationError, ValidationErrorKind, ValidationRules};
+use crate::Mode;
+
+/// Generate `per_len` inputs of each length in `lens` from the bytes of `alphabet`.
+///
+/// The inputs come from a xorshift generator with a fixed seed, so every run tests
+/// the same inputs.
+pub(crate) fn random_inputs(alphabet: &'static [u8], lens: Range<usize>, per_len: usize) -> impl Iterator<Item = Vec<u8>> {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+
+    lens.flat_map(move |len| (0..per_len).map(move |_| len)).map(move |len| {
+        (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            alphabet[(seed % alphabet.len() as u64) as usize]
+        }).collect()
+    })
+}
+
+/// The entry points of a backend.
+#[derive(Clone, Copy)]
+pub(crate) struct Backend {
+    pub url_decode: fn(&[u8], &mut Vec<u8>),
+    pub decoded_len: fn(&[u8]) -> usize,
+    pub needs_decoding: fn(&[u8], Mode) -> bool,
+    pub validate: fn(&[u8], &ValidationRules) -> Result<(), ValidationError>,
+}
+
+/// Check that `backend` gives the same results as the fallback for random inputs
+/// with `per_len` of each length in `lens`.
+pub(crate) fn assert_matches_fallback(backend: Backend, lens: Range<usize>, per_len: usize) {
+    for v in random_inputs(b"%+aF0g9\xc0", lens, per_len) {
+        let mut expected = Vec::new();
+        crate::fallback::url_decode(&v, &mut expected);
+        let mut result = Vec::new();
+        (backend.url_decode)(&v, &mut result);
+        assert_eq!(expected, result, "input {:?}", v);
+        assert_eq!(expected.len(), (backend.decoded_len)(&v), "input {:?}", v);
+        assert_eq!(expected != v, (backend.needs_decoding)(&v, Mode::Form), "input {:?}", v);
+
+        for rules in &[ValidationRules::default(), ValidationRules { rfc3986_chars_only: true }] {
+            assert_eq!(crate::fallback::validate(&v, rules), (backend.validate)(&v, rules), "input {:?}", v);
+        }
+    }
+}
+
+/// Check that `backend` counts valid, invalid and incomplete escapes.
+pub(crate) fn assert_decoded_len(backend: Backend) {
+    let decoded_len = backend.decoded_len;
+    assert_eq!(0, decoded_len(b""));
+    assert_eq!(1, decoded_len(b"%41"));
+    assert_eq!(2, decoded_len(b"%4"));
+    assert_eq!(2, decoded_len(b"%%41"));
+    assert_eq!(2, decoded_len(b"a+"));
+    assert_eq!(12, decoded_len(b"%41a%42b12345678"));
+    assert_eq!(44, decoded_len(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%41aaaaaaaaa%4g"));
+}
+
+/// Check that `backend` finds escapes in each mode, including one which spans two
+/// chunks.
+pub(crate) fn assert_needs_decoding(backend: Backend) {
+    let needs_decoding = backend.needs_decoding;
+    assert!(!needs_decoding(b"", Mode::Form));
+    assert!(!needs_decoding(b"abc%4g%", Mode::Path));
+    assert!(!needs_decoding(b"a+b", Mode::Path));
+    assert!(needs_decoding(b"a+b", Mode::Form));
+    assert!(needs_decoding(b"%41", Mode::Path));
+
+    // The escape spans two chunks.
+    assert!(needs_decoding(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%41aaaaaaaaa", Mode::Path));
+    assert!(needs_decoding(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%4A", Mode::Path));
+}
+
+/// Check that `backend` reports the first error in the input, including errors past
+/// the first chunk.
+pub(crate) fn assert_validate(backend: Backend) {
+    let validate = backend.validate;
+    let rules = ValidationRules::default();
+    let strict = ValidationRules { rfc3986_chars_only: true };
+
+    assert_eq!(Ok(()), validate(b"", &strict));
+    assert_eq!(Ok(()), validate(b"/a+b/%41?c=d#e", &strict));
+    assert_eq!(Ok(()), validate(b"a b", &rules));
+
+    let error = validate(b"%41%4", &rules).unwrap_err();
+    assert_eq!((3, ValidationErrorKind::InvalidEscape), (error.offset(), error.kind()));
+
+    let error = validate(b"a b%", &strict).unwrap_err();
+    assert_eq!((1, ValidationErrorKind::DisallowedByte), (error.offset(), error.kind()));
+
+    // The error is in the second chunk.
+    let error = validate(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa%41aaaaaaaaa%4g", &strict).unwrap_err();
+    assert_eq!((43, ValidationErrorKind::InvalidEscape), (error.offset(), error.kind()));
+
+    let error = validate(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\xc0", &strict).unwrap_err();
+    assert_eq!((40, ValidationErrorKind::DisallowedByte), (error.offset(), error.kind()));
+}
+
+/// `len` bytes of `a`, then `middle` and then 40 bytes of `b`.
+pub(crate) fn padded(len: usize, middle: &[u8]) -> Vec<u8> {
+    let mut v = vec![b'a'; len];
+    v.extend_from_slice(middle);
+    v.extend_from_slice(&[b'b'; 40]);
+    v
+}
+
+/// Call `f` with `len` and [`padded(len, middle)`](padded) for `len` from 0 to 39,
+/// so that every part of `middle` is at the end of the first chunk of every
+/// implementation.
+pub(crate) fn across_chunks(middle: &[u8], mut f: impl FnMut(usize, &[u8])) {
+    for len in 0..40 {
+        f(len, &padded(len, middle));
+    }
+}
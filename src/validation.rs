@@ -0,0 +1,85 @@
+//! Types for checking that input is well-formed percent-encoding without decoding it.
+
+use std::error::Error;
+use std::fmt;
+
+/// Rules for [`validate`](crate::validate).
+///
+/// By default only the escapes are checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValidationRules {
+    /// Reject bytes which RFC 3986 does not allow to appear unescaped in a URI.
+    ///
+    /// The allowed bytes are the unreserved and reserved characters, and `%`.
+    pub rfc3986_chars_only: bool,
+}
+
+/// Why a byte of the input is not well-formed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    /// A `%` which is not followed by two hex digits.
+    InvalidEscape,
+    /// A byte which is not allowed to appear unescaped.
+    DisallowedByte,
+}
+
+/// The first byte of the input which is not well-formed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    offset: usize,
+    kind: ValidationErrorKind,
+}
+
+impl ValidationError {
+    pub(crate) fn new(offset: usize, kind: ValidationErrorKind) -> Self {
+        ValidationError { offset, kind }
+    }
+
+    /// Offset of the offending byte in the input.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Why the byte at [`offset`](Self::offset) is not well-formed.
+    pub fn kind(&self) -> ValidationErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ValidationErrorKind::InvalidEscape => write!(f, "invalid percent escape at offset {}", self.offset),
+            ValidationErrorKind::DisallowedByte => write!(f, "disallowed byte at offset {}", self.offset),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Printable ASCII bytes which RFC 3986 does not allow in a URI.
+pub(crate) const EXCLUDED: &[u8; 9] = b"\"<>\\^`{|}";
+
+/// Check if RFC 3986 allows `byte` to appear unescaped in a URI.
+#[inline]
+pub(crate) fn is_allowed(byte: u8) -> bool {
+    byte > b' ' && byte < 0x7f && !EXCLUDED.contains(&byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_allowed, ValidationError, ValidationErrorKind};
+
+    #[test]
+    fn test_is_allowed() {
+        let allowed: Vec<u8> = (0..=255).filter(|&b| is_allowed(b)).collect();
+        let expected: &[u8] = b"!#$%&'()*+,-./0123456789:;=?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[]_abcdefghijklmnopqrstuvwxyz~";
+        assert_eq!(expected, &allowed[..]);
+    }
+
+    #[test]
+    fn test_display() {
+        let error = ValidationError::new(3, ValidationErrorKind::InvalidEscape);
+        assert_eq!("invalid percent escape at offset 3", error.to_string());
+    }
+}